@@ -0,0 +1,141 @@
+// the request headers the component reads
+const ALLOWED_HEADERS: &str = "content-type, x-request-id";
+const MAX_AGE: &str = "86400"; // let browsers cache preflight responses for a day
+
+/// Matches an `Origin` header against allowed origins, which can be `*`,
+/// exact origins such as `https://www.example.com`, or subdomain wildcards such as `https://*.example.com`.
+pub fn is_origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+    allowed_origins.iter().any(|allowed| {
+        let allowed = allowed.trim_end_matches('/').to_ascii_lowercase();
+        if allowed == "*" || allowed == origin {
+            return true;
+        }
+        match allowed.split_once("*.") {
+            Some((scheme, domain)) => origin
+                .strip_prefix(scheme)
+                .and_then(|host| host.strip_suffix(domain))
+                .and_then(|subdomain| subdomain.strip_suffix('.'))
+                .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains('/')),
+            None => false,
+        }
+    })
+}
+
+/// CORS headers added to every response. Nothing is added when no origin is allowed,
+/// so the endpoint can only be called from the same origin.
+pub fn headers(origin: Option<&str>, allowed_origins: &[String]) -> Vec<(&'static str, String)> {
+    if allowed_origins.is_empty() {
+        return vec![];
+    }
+    if allowed_origins.iter().any(|allowed| allowed == "*") {
+        return vec![("access-control-allow-origin", "*".to_string())];
+    }
+
+    // the response depends on the request's origin, caches must know it
+    let mut headers = vec![("vary", "Origin".to_string())];
+    if let Some(origin) = origin.filter(|origin| is_origin_allowed(origin, allowed_origins)) {
+        headers.push(("access-control-allow-origin", origin.to_string()));
+    }
+    headers
+}
+
+/// Additional headers for responses to preflight (OPTIONS) requests, allowing `allowed_methods`
+/// (see [`crate::router::allowed_methods`]) and the headers the component reads.
+pub fn preflight_headers(
+    origin: Option<&str>,
+    allowed_origins: &[String],
+    allowed_methods: &str,
+) -> Vec<(&'static str, String)> {
+    if !origin.is_some_and(|origin| is_origin_allowed(origin, allowed_origins)) {
+        return vec![];
+    }
+
+    vec![
+        ("access-control-allow-methods", allowed_methods.to_string()),
+        ("access-control-allow-headers", ALLOWED_HEADERS.to_string()),
+        ("access-control-max-age", MAX_AGE.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins(origins: &[&str]) -> Vec<String> {
+        origins.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_origin_allowed() {
+        let allowed = origins(&["https://www.example.com", "https://*.example.org"]);
+        assert!(is_origin_allowed("https://www.example.com", &allowed));
+        assert!(is_origin_allowed("https://WWW.example.com/", &allowed));
+        assert!(is_origin_allowed("https://app.example.org", &allowed));
+        assert!(is_origin_allowed("https://a.b.example.org", &allowed));
+        assert!(!is_origin_allowed("https://example.org", &allowed));
+        assert!(!is_origin_allowed("https://evilexample.org", &allowed));
+        assert!(!is_origin_allowed("http://app.example.org", &allowed));
+        assert!(!is_origin_allowed("https://example.com", &allowed));
+        assert!(is_origin_allowed("https://anything.test", &origins(&["*"])));
+        assert!(!is_origin_allowed("https://www.example.com", &[]));
+    }
+
+    #[test]
+    fn test_headers() {
+        let allowed = origins(&["https://www.example.com"]);
+        assert_eq!(headers(Some("https://www.example.com"), &[]), vec![]);
+        assert_eq!(
+            headers(Some("https://www.example.com"), &allowed),
+            vec![
+                ("vary", "Origin".to_string()),
+                (
+                    "access-control-allow-origin",
+                    "https://www.example.com".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            headers(Some("https://evil.example"), &allowed),
+            vec![("vary", "Origin".to_string())]
+        );
+        assert_eq!(
+            headers(None, &allowed),
+            vec![("vary", "Origin".to_string())]
+        );
+        assert_eq!(
+            headers(Some("https://evil.example"), &origins(&["*"])),
+            vec![("access-control-allow-origin", "*".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_preflight_headers() {
+        let allowed = origins(&["https://www.example.com"]);
+        assert_eq!(
+            preflight_headers(Some("https://www.example.com"), &allowed, "POST, OPTIONS"),
+            vec![
+                ("access-control-allow-methods", "POST, OPTIONS".to_string()),
+                (
+                    "access-control-allow-headers",
+                    "content-type, x-request-id".to_string()
+                ),
+                ("access-control-max-age", "86400".to_string()),
+            ]
+        );
+        assert_eq!(
+            preflight_headers(
+                Some("https://www.example.com"),
+                &allowed,
+                "GET, POST, OPTIONS"
+            )[0],
+            (
+                "access-control-allow-methods",
+                "GET, POST, OPTIONS".to_string()
+            )
+        );
+
+        assert!(preflight_headers(Some("https://evil.example"), &allowed, "POST").is_empty());
+        assert!(preflight_headers(None, &allowed, "POST").is_empty());
+    }
+}
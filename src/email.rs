@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+
+use crate::email_address::Mailbox;
+use crate::html;
+
+const MAX_RECIPIENTS: usize = 1000; // SendGrid limit for to + cc + bcc in a single personalization
+const MAX_SUBJECT_LENGTH: usize = 255; // in characters, longer subjects are truncated
+
+// headers providers don't allow to override, see https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send
+const RESERVED_HEADERS: &[&str] = &[
+    "x-sg-id",
+    "x-sg-eid",
+    "received",
+    "dkim-signature",
+    "content-type",
+    "content-transfer-encoding",
+    "to",
+    "from",
+    "subject",
+    "reply-to",
+    "cc",
+    "bcc",
+];
+
+/// A provider-neutral email, built from the request and settings, then sent by a
+/// [`MailProvider`](crate::provider::MailProvider). Values that end up in headers are sanitized
+/// by the constructor and setters, so every provider gets them without line breaks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub from: Mailbox,
+    pub recipients: Recipients,
+    pub reply_to: Vec<Mailbox>,
+    pub subject: String, // unused with a template, which defines its own subject
+    pub body: Option<MessageBody>, // used only if no template_id is provided
+    pub template_id: Option<String>,
+    pub template_data: Option<serde_json::Value>, // used only if template_id is provided
+    pub attachments: Vec<Attachment>,
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A file uploaded with the request, sent as an email attachment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Recipients {
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub bcc: Vec<String>,
+}
+
+impl Recipients {
+    /// Checks SendGrid's per-personalization limits before anything is sent:
+    /// at least one `to` address, at most 1000 recipients in total,
+    /// and no address appearing twice across to/cc/bcc.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.to.is_empty() {
+            return Err(anyhow::anyhow!("At least one recipient is required"));
+        }
+
+        let total = self.to.len() + self.cc.len() + self.bcc.len();
+        if total > MAX_RECIPIENTS {
+            return Err(anyhow::anyhow!(
+                "Too many recipients: {total} (maximum is {MAX_RECIPIENTS} across to, cc and bcc)"
+            ));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for email in self.to.iter().chain(&self.cc).chain(&self.bcc) {
+            if !seen.insert(email.to_lowercase()) {
+                return Err(anyhow::anyhow!(
+                    "Duplicate recipient '{email}' across to, cc and bcc"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Static email content, always sent as both text/plain and text/html.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageBody {
+    pub text: String,
+    pub html: String,
+}
+
+impl MessageBody {
+    /// The HTML part is generated from the text (escaped, newlines turned into `<br>`).
+    pub fn from_text(text: String) -> Self {
+        let html = html::text_to_html(&text);
+        Self { text, html }
+    }
+
+    /// The text part is generated from the HTML (tags removed).
+    pub fn from_html(html: String) -> Self {
+        let text = html::html_to_text(&html);
+        Self { text, html }
+    }
+}
+
+impl Email {
+    pub fn new(
+        email_from: String,
+        recipients: Recipients,
+        subject: String,
+        body: Option<MessageBody>,
+        template_id: Option<String>,
+        template_data: Option<serde_json::Value>,
+    ) -> Self {
+        let sanitize_all =
+            |addresses: Vec<String>| addresses.iter().map(|a| sanitize_header_value(a)).collect();
+        Self {
+            from: Mailbox {
+                email: sanitize_header_value(&email_from),
+                name: None,
+            },
+            recipients: Recipients {
+                to: sanitize_all(recipients.to),
+                cc: sanitize_all(recipients.cc),
+                bcc: sanitize_all(recipients.bcc),
+            },
+            reply_to: vec![],
+            subject: sanitize_subject(&subject),
+            body,
+            template_id: template_id.filter(|id| !id.is_empty()),
+            template_data,
+            attachments: vec![],
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the sender's display name, such as `John via Website`.
+    pub fn set_from_name(&mut self, name: String) -> &mut Self {
+        self.from.name = Some(sanitize_header_value(&name)).filter(|name| !name.is_empty());
+        self
+    }
+
+    pub fn set_reply_to(&mut self, mailboxes: Vec<Mailbox>) -> &mut Self {
+        self.reply_to = mailboxes.iter().map(sanitize_mailbox).collect();
+        self
+    }
+
+    pub fn add_attachment(&mut self, attachment: Attachment) -> &mut Self {
+        self.attachments.push(Attachment {
+            filename: sanitize_header_value(&attachment.filename),
+            content_type: sanitize_header_value(&attachment.content_type),
+            content: attachment.content,
+        });
+        self
+    }
+
+    /// Adds a custom header, rejecting invalid or reserved names.
+    pub fn add_header(&mut self, name: &str, value: &str) -> anyhow::Result<&mut Self> {
+        validate_header_name(name)?;
+        self.headers
+            .insert(name.to_string(), sanitize_header_value(value));
+        Ok(self)
+    }
+}
+
+/// Removes CR/LF and other control characters from a value that ends up in an email header,
+/// so user input can't inject additional headers. Line breaks are replaced by a single space.
+fn sanitize_header_value(value: &str) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    let mut line_break = false;
+    for c in value.chars() {
+        if matches!(c, '\r' | '\n' | '\u{85}' | '\u{2028}' | '\u{2029}') {
+            line_break = true;
+            continue;
+        }
+        if line_break {
+            sanitized.push(' ');
+            line_break = false;
+        }
+        if c == '\t' {
+            sanitized.push(' ');
+        } else if !c.is_control() {
+            sanitized.push(c);
+        }
+    }
+    sanitized.trim().to_string()
+}
+
+/// Sanitizes a subject, and truncates it to a length that mail clients display properly.
+fn sanitize_subject(subject: &str) -> String {
+    let subject = sanitize_header_value(subject);
+    match subject.char_indices().nth(MAX_SUBJECT_LENGTH) {
+        Some((end, _)) => subject[..end].trim_end().to_string(),
+        None => subject,
+    }
+}
+
+fn sanitize_mailbox(mailbox: &Mailbox) -> Mailbox {
+    Mailbox {
+        email: sanitize_header_value(&mailbox.email),
+        name: mailbox
+            .name
+            .as_deref()
+            .map(sanitize_header_value)
+            .filter(|name| !name.is_empty()),
+    }
+}
+
+/// Standard base64 encoding with padding, as expected for attachment contents.
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Header names must be printable ASCII without colon (RFC 5322), and not reserved.
+fn validate_header_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_graphic() && c != ':') {
+        return Err(anyhow::anyhow!(
+            "Invalid header name '{}'",
+            name.escape_debug()
+        ));
+    }
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(anyhow::anyhow!("Header '{name}' can't be overridden"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email::new(
+            "from@example.com".to_string(),
+            Recipients {
+                to: vec!["to@example.com".to_string()],
+                ..Default::default()
+            },
+            "Hello".to_string(),
+            Some(MessageBody::from_text("Hi".to_string())),
+            Some(String::new()),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_new_ignores_empty_template_id() {
+        assert_eq!(email().template_id, None);
+    }
+
+    #[test]
+    fn test_message_body_from_text() {
+        let body = MessageBody::from_text("Hi <you>\nbye".to_string());
+        assert_eq!(body.text, "Hi <you>\nbye");
+        assert_eq!(body.html, "Hi &lt;you&gt;<br>\nbye");
+    }
+
+    #[test]
+    fn test_add_header() {
+        let mut email = email();
+        email.add_header("X-Source", "website").unwrap();
+        assert_eq!(email.headers["X-Source"], "website");
+
+        let invalid = [
+            ("", "Invalid header name ''"),
+            ("X-Source\r\nBcc", "Invalid header name 'X-Source\\r\\nBcc'"),
+            ("X Source", "Invalid header name 'X Source'"),
+            ("X-Source:", "Invalid header name 'X-Source:'"),
+            ("X-Sourcé", "Invalid header name 'X-Sourcé'"),
+            ("BCC", "Header 'BCC' can't be overridden"),
+            ("Reply-To", "Header 'Reply-To' can't be overridden"),
+        ];
+        for (name, error) in invalid {
+            assert_eq!(
+                email.add_header(name, "value").err().unwrap().to_string(),
+                error
+            );
+        }
+    }
+
+    // payloads trying to add headers or recipients through header-like fields
+    const INJECTION_PAYLOADS: &[(&str, &str)] = &[
+        (
+            "Hello\r\nBcc: victim@example.com",
+            "Hello Bcc: victim@example.com",
+        ),
+        (
+            "Hello\nBcc: victim@example.com",
+            "Hello Bcc: victim@example.com",
+        ),
+        (
+            "Hello\rBcc: victim@example.com",
+            "Hello Bcc: victim@example.com",
+        ),
+        ("Hello\r\n\r\n<html>body</html>", "Hello <html>body</html>"),
+        (
+            "Hello\r\n Bcc: folded@example.com",
+            "Hello  Bcc: folded@example.com",
+        ),
+        (
+            "Hello\u{85}Bcc: nel@example.com",
+            "Hello Bcc: nel@example.com",
+        ),
+        (
+            "Hello\u{2028}Bcc: ls@example.com",
+            "Hello Bcc: ls@example.com",
+        ),
+        ("Hello\0World\u{7f}\u{1b}[31m", "HelloWorld[31m"),
+        ("Hello\tWorld", "Hello World"),
+        ("\r\nBcc: victim@example.com\r\n", "Bcc: victim@example.com"),
+        ("Plain subject", "Plain subject"),
+        ("Unicode subject: café ☕", "Unicode subject: café ☕"),
+    ];
+
+    #[test]
+    fn test_sanitize_header_value() {
+        for (input, expected) in INJECTION_PAYLOADS {
+            let sanitized = sanitize_header_value(input);
+            assert_eq!(&sanitized, expected, "{input:?}");
+            assert!(!sanitized.contains(['\r', '\n']));
+        }
+    }
+
+    #[test]
+    fn test_new_and_setters_sanitize() {
+        for (input, expected) in INJECTION_PAYLOADS {
+            let mut email = Email::new(
+                "from@example.com\r\nBcc: victim@example.com".to_string(),
+                Recipients {
+                    to: vec![format!("to@example.com{input}")],
+                    cc: vec![format!("cc@example.com{input}")],
+                    bcc: vec![format!("bcc@example.com{input}")],
+                },
+                input.to_string(),
+                Some(MessageBody::from_text("Hi".to_string())),
+                None,
+                None,
+            );
+            email.set_from_name(format!("John{input}"));
+            email.set_reply_to(vec![Mailbox {
+                email: format!("reply@example.com{input}"),
+                name: Some(format!("John{input}")),
+            }]);
+            email.add_attachment(Attachment {
+                filename: format!("cv{input}.pdf"),
+                content_type: format!("application/pdf{input}"),
+                content: vec![],
+            });
+            email
+                .add_header("X-Source", &format!("website{input}"))
+                .unwrap();
+
+            assert_eq!(email.subject, *expected);
+            assert_eq!(email.from.email, "from@example.com Bcc: victim@example.com");
+            let values = [
+                &email.from.email,
+                email.from.name.as_ref().unwrap(),
+                &email.recipients.to[0],
+                &email.recipients.cc[0],
+                &email.recipients.bcc[0],
+                &email.subject,
+                &email.reply_to[0].email,
+                email.reply_to[0].name.as_ref().unwrap(),
+                &email.attachments[0].filename,
+                &email.attachments[0].content_type,
+                &email.headers["X-Source"],
+            ];
+            for value in values {
+                assert!(!value.contains(['\r', '\n']), "{value:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_from_name_ignores_empty_names() {
+        let mut email = email();
+        email.set_from_name("\r\n".to_string());
+        assert_eq!(email.from.name, None);
+    }
+
+    #[test]
+    fn test_sanitize_subject() {
+        assert_eq!(sanitize_subject(" Hello\r\nWorld "), "Hello World");
+        let long = "é".repeat(300);
+        assert_eq!(sanitize_subject(&long), "é".repeat(255));
+        assert_eq!(
+            sanitize_subject(&format!("{} x", "a".repeat(254))),
+            "a".repeat(254)
+        );
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0xfd, 0x00]), "//79AA==");
+    }
+
+    #[test]
+    fn test_recipients_validate_ok() {
+        let recipients = Recipients {
+            to: vec!["a@example.com".to_string()],
+            cc: vec!["b@example.com".to_string()],
+            bcc: vec!["c@example.com".to_string()],
+        };
+        assert!(recipients.validate().is_ok());
+    }
+
+    #[test]
+    fn test_recipients_validate_missing_to() {
+        let recipients = Recipients {
+            cc: vec!["b@example.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            recipients.validate().unwrap_err().to_string(),
+            "At least one recipient is required"
+        );
+    }
+
+    #[test]
+    fn test_recipients_validate_duplicates() {
+        let recipients = Recipients {
+            to: vec!["a@example.com".to_string()],
+            cc: vec![],
+            bcc: vec!["A@Example.com".to_string()],
+        };
+        assert_eq!(
+            recipients.validate().unwrap_err().to_string(),
+            "Duplicate recipient 'A@Example.com' across to, cc and bcc"
+        );
+    }
+
+    #[test]
+    fn test_recipients_validate_too_many() {
+        let recipients = Recipients {
+            to: (0..600).map(|i| format!("to{i}@example.com")).collect(),
+            cc: (0..300).map(|i| format!("cc{i}@example.com")).collect(),
+            bcc: (0..101).map(|i| format!("bcc{i}@example.com")).collect(),
+        };
+        assert_eq!(
+            recipients.validate().unwrap_err().to_string(),
+            "Too many recipients: 1001 (maximum is 1000 across to, cc and bcc)"
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Email address validation, following RFC 5322 (addr-spec and name-addr forms)
+//! with the length limits of RFC 5321 and IDN domains converted to punycode.
+
+const MAX_ADDRESS_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// An address with its optional display name, such as `John Doe <john@example.com>`.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Mailbox {
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Validates an address such as `john@example.com` or `John Doe <john@example.com>`,
+/// and returns the normalized address: display name removed, domain lowercased and in punycode.
+pub fn parse(input: &str) -> anyhow::Result<String> {
+    parse_mailbox(input).map(|mailbox| mailbox.email)
+}
+
+/// Same as [`parse`], keeping the display name (unquoted) if any.
+pub fn parse_mailbox(input: &str) -> anyhow::Result<Mailbox> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(anyhow::anyhow!("empty address"));
+    }
+    if input.chars().any(|c| c.is_control()) {
+        return Err(anyhow::anyhow!("control characters are not allowed"));
+    }
+
+    let (name, addr_spec) = match input.strip_suffix('>') {
+        Some(name_addr) => {
+            let (name, addr_spec) = name_addr
+                .rsplit_once('<')
+                .ok_or_else(|| anyhow::anyhow!("unbalanced angle brackets"))?;
+            let name = name.trim();
+            validate_display_name(name)?;
+            (unquote(name), addr_spec)
+        }
+        None => (String::new(), input),
+    };
+    if addr_spec.contains(['<', '>']) {
+        return Err(anyhow::anyhow!("unbalanced angle brackets"));
+    }
+
+    let (local_part, domain) = addr_spec
+        .rsplit_once('@')
+        .ok_or_else(|| anyhow::anyhow!("missing '@'"))?;
+    validate_local_part(local_part)?;
+    let domain = normalize_domain(domain)?;
+
+    let address = format!("{local_part}@{domain}");
+    if address.len() > MAX_ADDRESS_LENGTH {
+        return Err(anyhow::anyhow!(
+            "address is longer than {MAX_ADDRESS_LENGTH} characters"
+        ));
+    }
+    Ok(Mailbox {
+        email: address,
+        name: Some(name).filter(|name| !name.is_empty()),
+    })
+}
+
+/// Splits a comma-separated list of addresses, ignoring commas within quoted display names.
+pub fn split_list(value: &str) -> Vec<String> {
+    let mut addresses = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                addresses.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    addresses.push(current);
+
+    addresses
+        .into_iter()
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+fn validate_display_name(name: &str) -> anyhow::Result<()> {
+    if let Some(quoted) = name.strip_prefix('"') {
+        let content = quoted
+            .strip_suffix('"')
+            .ok_or_else(|| anyhow::anyhow!("unterminated quoted display name"))?;
+        return validate_quoted_content(content);
+    }
+
+    // unquoted display names are a phrase of words, non-ASCII characters are allowed (RFC 6532)
+    match name
+        .chars()
+        .find(|c| !(is_atext(*c) || *c == ' ' || *c == '.' || !c.is_ascii()))
+    {
+        Some(c) => Err(anyhow::anyhow!(
+            "invalid character '{c}' in display name, it must be quoted"
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes the quotes and escapes of a quoted display name.
+fn unquote(name: &str) -> String {
+    let Some(quoted) = name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    else {
+        return name.to_string();
+    };
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            _ => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+fn validate_quoted_content(content: &str) -> anyhow::Result<()> {
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("unterminated escape sequence"))?;
+            }
+            '"' => return Err(anyhow::anyhow!("unescaped quote in quoted string")),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn validate_local_part(local_part: &str) -> anyhow::Result<()> {
+    if local_part.is_empty() {
+        return Err(anyhow::anyhow!("missing local part before '@'"));
+    }
+    if local_part.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(anyhow::anyhow!(
+            "local part is longer than {MAX_LOCAL_PART_LENGTH} characters"
+        ));
+    }
+
+    if let Some(quoted) = local_part.strip_prefix('"') {
+        let content = quoted
+            .strip_suffix('"')
+            .ok_or_else(|| anyhow::anyhow!("unterminated quoted local part"))?;
+        if !content.is_ascii() {
+            return Err(anyhow::anyhow!("non-ASCII characters in local part"));
+        }
+        return validate_quoted_content(content);
+    }
+
+    // dot-atom: atoms separated by single dots
+    if local_part.split('.').any(str::is_empty) {
+        return Err(anyhow::anyhow!(
+            "local part can't start or end with a dot, or contain consecutive dots"
+        ));
+    }
+    match local_part.chars().find(|c| !(is_atext(*c) || *c == '.')) {
+        Some(c) => Err(anyhow::anyhow!("invalid character '{c}' in local part")),
+        None => Ok(()),
+    }
+}
+
+fn normalize_domain(domain: &str) -> anyhow::Result<String> {
+    if domain.is_empty() {
+        return Err(anyhow::anyhow!("missing domain after '@'"));
+    }
+    if domain.starts_with('[') {
+        return Err(anyhow::anyhow!("IP address literals are not supported"));
+    }
+
+    // internationalized domains are converted to punycode (xn--...)
+    let domain =
+        idna::domain_to_ascii(domain).map_err(|_| anyhow::anyhow!("invalid domain '{domain}'"))?;
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err(anyhow::anyhow!("domain '{domain}' must contain a dot"));
+    }
+    for label in &labels {
+        if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+            return Err(anyhow::anyhow!(
+                "domain labels must be between 1 and {MAX_LABEL_LENGTH} characters"
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(anyhow::anyhow!(
+                "domain labels can't start or end with a hyphen"
+            ));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-'))
+        {
+            return Err(anyhow::anyhow!("invalid character '{c}' in domain"));
+        }
+    }
+    if labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("top-level domain can't be numeric"));
+    }
+
+    Ok(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_valid() {
+        let valid = [
+            ("john@example.com", "john@example.com"),
+            ("  john@example.com ", "john@example.com"),
+            ("John.Doe+tag@Example.COM", "John.Doe+tag@example.com"),
+            ("o'brien@sub.example.co.uk", "o'brien@sub.example.co.uk"),
+            ("\"john doe\"@example.com", "\"john doe\"@example.com"),
+            ("John Doe <john@example.com>", "john@example.com"),
+            ("\"Doe, John\" <john@example.com>", "john@example.com"),
+            ("Élodie <elodie@example.com>", "elodie@example.com"),
+            ("<john@example.com>", "john@example.com"),
+            ("info@bücher.example", "info@xn--bcher-kva.example"),
+            ("contact@例え.jp", "contact@xn--r8jz45g.jp"),
+        ];
+        for (input, expected) in valid {
+            assert_eq!(parse(input).unwrap(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_parse_mailbox() {
+        let mailbox = |email: &str, name: Option<&str>| Mailbox {
+            email: email.to_string(),
+            name: name.map(String::from),
+        };
+        assert_eq!(
+            parse_mailbox("john@example.com").unwrap(),
+            mailbox("john@example.com", None)
+        );
+        assert_eq!(
+            parse_mailbox("John Doe <john@Example.com>").unwrap(),
+            mailbox("john@example.com", Some("John Doe"))
+        );
+        assert_eq!(
+            parse_mailbox(r#""Doe, \"Johnny\"" <john@example.com>"#).unwrap(),
+            mailbox("john@example.com", Some(r#"Doe, "Johnny""#))
+        );
+        assert_eq!(
+            parse_mailbox("<john@example.com>").unwrap(),
+            mailbox("john@example.com", None)
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let invalid = [
+            ("", "empty address"),
+            ("john", "missing '@'"),
+            ("@example.com", "missing local part before '@'"),
+            ("john@", "missing domain after '@'"),
+            ("john@localhost", "domain 'localhost' must contain a dot"),
+            (
+                "john@example..com",
+                "domain labels must be between 1 and 63 characters",
+            ),
+            (
+                "john@-example.com",
+                "domain labels can't start or end with a hyphen",
+            ),
+            ("john@exa_mple.com", "invalid character '_' in domain"),
+            ("john@example.123", "top-level domain can't be numeric"),
+            ("john@[127.0.0.1]", "IP address literals are not supported"),
+            (
+                ".john@example.com",
+                "local part can't start or end with a dot, or contain consecutive dots",
+            ),
+            (
+                "jo..hn@example.com",
+                "local part can't start or end with a dot, or contain consecutive dots",
+            ),
+            ("jo hn@example.com", "invalid character ' ' in local part"),
+            ("jöhn@example.com", "invalid character 'ö' in local part"),
+            ("\"jo\"hn\"@example.com", "unescaped quote in quoted string"),
+            (
+                "john@example.com\r\nBcc: evil@example.com",
+                "control characters are not allowed",
+            ),
+            ("John <john@example.com", "unbalanced angle brackets"),
+            (
+                "John, Doe <john@example.com>",
+                "invalid character ',' in display name, it must be quoted",
+            ),
+            ("John <john@example.com> x", "unbalanced angle brackets"),
+        ];
+        for (input, error) in invalid {
+            assert_eq!(parse(input).unwrap_err().to_string(), error, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_parse_length_limits() {
+        let local_part = "a".repeat(64);
+        assert!(parse(&format!("{local_part}@example.com")).is_ok());
+        assert_eq!(
+            parse(&format!("a{local_part}@example.com"))
+                .unwrap_err()
+                .to_string(),
+            "local part is longer than 64 characters"
+        );
+
+        let label = "b".repeat(64);
+        assert_eq!(
+            parse(&format!("john@{label}.com")).unwrap_err().to_string(),
+            "domain labels must be between 1 and 63 characters"
+        );
+
+        let domain = [
+            "c".repeat(63),
+            "c".repeat(63),
+            "c".repeat(63),
+            "com".to_string(),
+        ]
+        .join(".");
+        assert_eq!(
+            parse(&format!("{local_part}@{domain}"))
+                .unwrap_err()
+                .to_string(),
+            "address is longer than 254 characters"
+        );
+    }
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(
+            split_list(r#"a@example.com, "Doe, John" <john@example.com>,, b@example.com "#),
+            vec![
+                "a@example.com",
+                "\"Doe, John\" <john@example.com>",
+                "b@example.com"
+            ]
+        );
+        assert!(split_list("").is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::schema::ValidationError;
+
+/// Stable, machine-readable error codes, so clients don't have to match on messages.
+/// Serialized with [`ErrorCode::as_str`], also used in redirect URLs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// the component settings are missing or invalid
+    InvalidSettings,
+    MethodNotAllowed,
+    /// the body can't be read or parsed (JSON, form or multipart)
+    InvalidBody,
+    /// the body is larger than the attachments allowed by settings
+    PayloadTooLarge,
+    MissingField,
+    /// a field has the wrong type or value
+    InvalidField,
+    InvalidEmail,
+    /// no recipient, too many or duplicates
+    InvalidRecipients,
+    /// an uploaded file has a type or size that is not allowed
+    InvalidAttachment,
+    /// the body doesn't match the `schema` setting, details are in `errors`
+    ValidationFailed,
+    /// any other invalid request
+    InvalidRequest,
+    /// SendGrid refused the email, or couldn't be reached (details are only logged)
+    UpstreamError,
+    /// SendGrid's rate limit was reached
+    RateLimited,
+    /// the email service didn't respond within the `timeout` setting
+    Timeout,
+    /// the email couldn't be prepared for the email service, retrying won't help
+    InternalError,
+}
+
+impl ErrorCode {
+    /// The code as sent to clients, the single source for serialization.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidSettings => "invalid_settings",
+            ErrorCode::MethodNotAllowed => "method_not_allowed",
+            ErrorCode::InvalidBody => "invalid_body",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::MissingField => "missing_field",
+            ErrorCode::InvalidField => "invalid_field",
+            ErrorCode::InvalidEmail => "invalid_email",
+            ErrorCode::InvalidRecipients => "invalid_recipients",
+            ErrorCode::InvalidAttachment => "invalid_attachment",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::UpstreamError => "upstream_error",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::InternalError => "internal_error",
+        }
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            ErrorCode::InvalidSettings | ErrorCode::InternalError => 500,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::UpstreamError => 502,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::Timeout => 504,
+            _ => 400,
+        }
+    }
+}
+
+impl serde::Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// An error returned to the client as `{"error": {"code": ..., "message": ..., ...}}`.
+/// Functions returning `anyhow::Result` can return it too, to give their errors a code.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// the request field at fault, such as `email` or `data.name`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ValidationError>,
+    /// upstream details, only set in debug mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Box<serde_json::Value>>,
+    /// set when the response is built, to correlate with logs
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub request_id: String,
+    #[serde(skip)]
+    pub status: u16,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            field: None,
+            errors: vec![],
+            details: None,
+            request_id: String::new(),
+            status: code.status(),
+        }
+    }
+
+    pub fn with_field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<ValidationError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// The JSON response body.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ "error": self }).to_string()
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Keeps the code of errors created as `ApiError`, others are generic invalid requests.
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<ApiError>() {
+            Ok(error) => error,
+            Err(error) => ApiError::new(ErrorCode::InvalidRequest, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut error =
+            ApiError::new(ErrorCode::MissingField, "Missing \"email\" field").with_field("email");
+        error.request_id = "abc".to_string();
+        assert_eq!(error.status, 400);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&error.to_json()).unwrap(),
+            serde_json::json!({
+                "error": {
+                    "code": "missing_field",
+                    "message": "Missing \"email\" field",
+                    "field": "email",
+                    "request_id": "abc",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_anyhow() {
+        let error: anyhow::Error = ApiError::new(ErrorCode::RateLimited, "Slow down").into();
+        assert_eq!(error.to_string(), "Slow down");
+        let error = ApiError::from(error);
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(error.status, 429);
+
+        let error = ApiError::from(anyhow::anyhow!("Something else"));
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+        assert_eq!(error.message, "Something else");
+    }
+
+    #[test]
+    fn test_error_code_as_str() {
+        let codes = [
+            ErrorCode::InvalidSettings,
+            ErrorCode::MethodNotAllowed,
+            ErrorCode::InvalidBody,
+            ErrorCode::PayloadTooLarge,
+            ErrorCode::MissingField,
+            ErrorCode::InvalidField,
+            ErrorCode::InvalidEmail,
+            ErrorCode::InvalidRecipients,
+            ErrorCode::InvalidAttachment,
+            ErrorCode::ValidationFailed,
+            ErrorCode::InvalidRequest,
+            ErrorCode::UpstreamError,
+            ErrorCode::RateLimited,
+            ErrorCode::Timeout,
+            ErrorCode::InternalError,
+        ];
+        for code in codes {
+            // the snake_case form of the variant name, in JSON bodies and redirect URLs alike
+            let name = format!("{code:?}");
+            let mut expected = String::new();
+            for (i, c) in name.char_indices() {
+                if c.is_ascii_uppercase() && i > 0 {
+                    expected.push('_');
+                }
+                expected.push(c.to_ascii_lowercase());
+            }
+            assert_eq!(code.as_str(), expected);
+            assert_eq!(serde_json::to_value(code).unwrap(), expected);
+        }
+    }
+}
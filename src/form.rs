@@ -0,0 +1,181 @@
+use serde_json::{Map, Value};
+
+const URLENCODED: &str = "application/x-www-form-urlencoded";
+
+/// Checks whether a content type such as `application/x-www-form-urlencoded; charset=utf-8`
+/// designates a url-encoded form.
+pub fn is_urlencoded(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .eq_ignore_ascii_case(URLENCODED)
+}
+
+/// Parses an `application/x-www-form-urlencoded` body, see [`fields_to_json`].
+pub fn parse_urlencoded(body: &[u8]) -> anyhow::Result<Value> {
+    fields_to_json(form_urlencoded::parse(body).into_owned().collect())
+}
+
+/// Converts form fields into the same JSON structure as a JSON request body.
+/// Repeated keys become arrays, and bracketed keys become nested objects:
+/// `data[name]=John` gives `{"data": {"name": "John"}}`, and `tags[]=a` always gives an array.
+pub fn fields_to_json(fields: Vec<(String, String)>) -> anyhow::Result<Value> {
+    let mut object = Map::new();
+    for (key, value) in fields {
+        let path = parse_key(&key);
+        insert(&mut object, &path, Value::String(value))
+            .map_err(|_| anyhow::anyhow!("Conflicting form fields for '{key}'"))?;
+    }
+    Ok(Value::Object(object))
+}
+
+/// Splits `data[address][city]` into `["data", "address", "city"]`.
+/// Keys that are not properly bracketed are used as is.
+fn parse_key(key: &str) -> Vec<&str> {
+    let Some(start) = key.find('[').filter(|start| *start > 0) else {
+        return vec![key];
+    };
+
+    let mut path = vec![&key[..start]];
+    let mut rest = &key[start..];
+    while let Some(segment) = rest.strip_prefix('[') {
+        let Some(end) = segment.find(']') else {
+            return vec![key];
+        };
+        path.push(&segment[..end]);
+        rest = &segment[end + 1..];
+    }
+    if !rest.is_empty() {
+        return vec![key];
+    }
+    path
+}
+
+fn insert(object: &mut Map<String, Value>, path: &[&str], value: Value) -> Result<(), ()> {
+    let key = path[0].to_string();
+    match &path[1..] {
+        // plain key, repeated keys become arrays
+        [] => match object.get_mut(&key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(Value::Object(_)) => return Err(()),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                object.insert(key, value);
+            }
+        },
+        // `key[]`, always an array
+        [""] => match object.entry(key).or_insert_with(|| Value::Array(vec![])) {
+            Value::Array(values) => values.push(value),
+            _ => return Err(()),
+        },
+        // `key[nested]...`
+        rest => match object
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(nested) => insert(nested, rest, value)?,
+            _ => return Err(()),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fields_to_json() {
+        let json = fields_to_json(fields(&[
+            ("email", "a@example.com"),
+            ("message", "Hello"),
+            ("email", "b@example.com"),
+            ("email", "c@example.com"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "email": ["a@example.com", "b@example.com", "c@example.com"],
+                "message": "Hello",
+            })
+        );
+    }
+
+    #[test]
+    fn test_fields_to_json_brackets() {
+        let json = fields_to_json(fields(&[
+            ("data[name]", "John"),
+            ("data[address][city]", "Paris"),
+            ("data[tags][]", "a"),
+            ("cc[]", "cc@example.com"),
+            ("weird[key", "kept"),
+            ("[empty]", "kept"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "data": {
+                    "name": "John",
+                    "address": {"city": "Paris"},
+                    "tags": ["a"],
+                },
+                "cc": ["cc@example.com"],
+                "weird[key": "kept",
+                "[empty]": "kept",
+            })
+        );
+    }
+
+    #[test]
+    fn test_fields_to_json_conflict() {
+        let result = fields_to_json(fields(&[("data", "x"), ("data[name]", "John")]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Conflicting form fields for 'data[name]'"
+        );
+
+        let result = fields_to_json(fields(&[("data[name]", "John"), ("data", "x")]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_urlencoded() {
+        let json = parse_urlencoded(
+            b"email=test%40example.com&message=Hello+world%21&data%5Bname%5D=John&email=other@example.com",
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "email": ["test@example.com", "other@example.com"],
+                "message": "Hello world!",
+                "data": {"name": "John"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_urlencoded() {
+        assert!(is_urlencoded("application/x-www-form-urlencoded"));
+        assert!(is_urlencoded(
+            "Application/X-WWW-Form-Urlencoded; charset=UTF-8"
+        ));
+        assert!(!is_urlencoded("application/json"));
+        assert!(!is_urlencoded(""));
+    }
+}
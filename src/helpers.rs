@@ -1,12 +1,18 @@
+use crate::error::{ApiError, ErrorCode};
 use crate::world::bindings::exports::wasi::http::incoming_handler::ResponseOutparam;
 use crate::world::bindings::wasi::http::types::{
     Fields, IncomingRequest, OutgoingBody, OutgoingResponse,
 };
 use crate::world::bindings::wasi::io::streams::StreamError;
+use crate::world::bindings::wasi::random::random::get_random_u64;
 use std::collections::HashMap;
 
+const MAX_WRITE_SIZE: usize = 4096;
+
+/// The response model, only turned into a WASI response when sent, so handlers can be tested natively.
+#[derive(Debug)]
 pub struct ResponseBuilder {
-    headers: Fields,
+    headers: Vec<(String, String)>,
     status_code: u16,
     body_content: Option<String>,
 }
@@ -20,16 +26,17 @@ impl Default for ResponseBuilder {
 impl ResponseBuilder {
     pub fn new() -> Self {
         ResponseBuilder {
-            headers: Fields::new(),
+            headers: Vec::new(),
             status_code: 200,
             body_content: None,
         }
     }
 
+    /// Replaces any header with the same name.
     pub fn set_header(&mut self, key: &str, value: &str) -> &mut Self {
-        let _ = self
-            .headers
-            .set(key, vec![value.as_bytes().to_vec()].as_slice());
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+        self.headers.push((key.to_string(), value.to_string()));
         self
     }
 
@@ -43,18 +50,56 @@ impl ResponseBuilder {
         self
     }
 
-    pub fn send(self, resp: ResponseOutparam) {
-        let resp_tx = OutgoingResponse::new(self.headers);
+    /// Sends the response. Once headers are sent, write errors can only be logged: the body
+    /// is then left unfinished, so the runtime reports the response as incomplete.
+    pub fn send(self, resp: ResponseOutparam, request_id: &str) {
+        let headers = Fields::new();
+        for (key, value) in &self.headers {
+            let _ = headers.set(key, &[value.as_bytes().to_vec()]);
+        }
+        let resp_tx = OutgoingResponse::new(headers);
         let _ = resp_tx.set_status_code(self.status_code);
 
-        let body = resp_tx.body().unwrap();
+        let Ok(body) = resp_tx.body() else {
+            eprintln!("[{request_id}] Failed to get response body");
+            ResponseOutparam::set(resp, Ok(resp_tx));
+            return;
+        };
         ResponseOutparam::set(resp, Ok(resp_tx));
-        let stream = body.write().unwrap();
-        if let Some(body_content) = self.body_content {
-            stream.write(body_content.as_bytes()).unwrap();
+        let Ok(stream) = body.write() else {
+            eprintln!("[{request_id}] Failed to get response stream");
+            return;
+        };
+        let content = self.body_content.unwrap_or_default();
+        if let Err(e) = write_all(content.as_bytes(), |chunk| {
+            stream.blocking_write_and_flush(chunk)
+        }) {
+            eprintln!("[{request_id}] Failed to write response body: {e}");
+            return;
         }
         drop(stream);
-        let _ = OutgoingBody::finish(body, None);
+        if let Err(e) = OutgoingBody::finish(body, None) {
+            eprintln!("[{request_id}] Failed to finish response body: {e}");
+        }
+    }
+}
+
+// accessors to check responses in tests
+#[cfg(test)]
+impl ResponseBuilder {
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn body(&self) -> Option<&str> {
+        self.body_content.as_deref()
     }
 }
 
@@ -72,39 +117,61 @@ pub fn parse_headers(headers: &Fields) -> HashMap<String, Vec<String>> {
     output
 }
 
-pub fn parse_body(req: IncomingRequest) -> Result<Vec<u8>, String> {
-    let mut request_body = Vec::new();
-    let stream = match req.consume() {
-        Ok(stream) => stream,
-        Err(_e) => {
-            return Err("Failed to consume request stream".to_string());
-        }
-    };
-    let stream = match stream.stream() {
-        Ok(stream) => stream,
-        Err(_e) => {
-            return Err("Failed to get request stream: ".to_string());
-        }
-    };
+/// Reads the whole request body, rejecting it as soon as it exceeds `max_size`.
+pub fn parse_body(
+    req: IncomingRequest,
+    headers: &HashMap<String, Vec<String>>,
+    max_size: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let content_length = headers
+        .get("content-length")
+        .and_then(|values| values.first())
+        .and_then(|value| value.trim().parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_size) {
+        return Err(too_large(max_size));
+    }
+
+    let invalid = |message: &str| ApiError::new(ErrorCode::InvalidBody, message);
+    let body = req
+        .consume()
+        .map_err(|()| invalid("Failed to consume request stream"))?;
+    let stream = body
+        .stream()
+        .map_err(|()| invalid("Failed to get request stream"))?;
+    read_to_end(|| stream.blocking_read(65536), max_size)
+}
 
+/// Reads chunks until the stream is closed. Empty chunks only mean that no data is available yet.
+fn read_to_end(
+    mut read: impl FnMut() -> Result<Vec<u8>, StreamError>,
+    max_size: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let mut body = Vec::new();
     loop {
-        match stream.read(4096) {
+        match read() {
             Ok(chunk) => {
-                if chunk.is_empty() {
-                    break;
+                if body.len() + chunk.len() > max_size {
+                    return Err(too_large(max_size));
                 }
-                request_body.extend_from_slice(&chunk);
-            }
-            Err(StreamError::Closed) => {
-                // Stream is closed, we can stop reading
-                break;
+                body.extend_from_slice(&chunk);
             }
+            Err(StreamError::Closed) => return Ok(body),
             Err(e) => {
-                return Err(format!("Failed to read from request stream: {e}"));
+                let message = format!("Failed to read from request stream: {e}");
+                return Err(ApiError::new(ErrorCode::InvalidBody, message));
             }
         }
     }
-    Ok(request_body)
+}
+
+/// Writes in chunks, as a single blocking write accepts at most 4096 bytes.
+fn write_all<E>(content: &[u8], write: impl FnMut(&[u8]) -> Result<(), E>) -> Result<(), E> {
+    content.chunks(MAX_WRITE_SIZE).try_for_each(write)
+}
+
+fn too_large(max_size: usize) -> ApiError {
+    let message = format!("Request body is larger than {max_size} bytes");
+    ApiError::new(ErrorCode::PayloadTooLarge, message)
 }
 
 pub fn build_response(body: &str, status_code: u16, content_type: &str) -> ResponseBuilder {
@@ -125,7 +192,75 @@ pub fn build_response_json(body: &str, status_code: u16) -> ResponseBuilder {
     build_response(body, status_code, "application/json")
 }
 
-pub fn build_response_json_error(message: &str, status_code: u16) -> ResponseBuilder {
-    let body = format!("{{\"error\": \"{message}\"}}");
-    build_response_json(&body, status_code)
+/// Redirects with a 303 See Other, so browsers follow up with a GET (Post/Redirect/Get).
+pub fn build_response_redirect(location: &str) -> ResponseBuilder {
+    let mut builder = ResponseBuilder::new();
+    builder
+        .set_header("location", location)
+        .set_status_code(303);
+    builder
+}
+
+pub fn build_response_json_error(error: &ApiError) -> ResponseBuilder {
+    build_response_json(&error.to_json(), error.status)
+}
+
+/// Random identifier for requests that don't come with one.
+pub fn generate_request_id() -> String {
+    let (high, low) = (get_random_u64(), get_random_u64());
+    format!("{high:016x}{low:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream returning the given chunks, then closed.
+    fn chunks(chunks: Vec<&'static [u8]>) -> impl FnMut() -> Result<Vec<u8>, StreamError> {
+        let mut chunks = chunks.into_iter();
+        move || chunks.next().map(<[u8]>::to_vec).ok_or(StreamError::Closed)
+    }
+
+    #[test]
+    fn test_read_to_end() {
+        // empty chunks don't end the body
+        let body = read_to_end(chunks(vec![b"Hello", b"", b", world!"]), 100).unwrap();
+        assert_eq!(body, b"Hello, world!");
+
+        let body = read_to_end(chunks(vec![b"12345", b"67890"]), 10).unwrap();
+        assert_eq!(body.len(), 10);
+
+        let error = read_to_end(chunks(vec![b"12345", b"67890", b"1"]), 10).unwrap_err();
+        assert_eq!(
+            (error.code, error.status),
+            (ErrorCode::PayloadTooLarge, 413)
+        );
+        assert_eq!(error.message, "Request body is larger than 10 bytes");
+    }
+
+    #[test]
+    fn test_write_all() {
+        let content = "é".repeat(5000);
+        let mut chunks = vec![];
+        write_all(content.as_bytes(), |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![4096, 4096, 1808]
+        );
+        assert_eq!(chunks.concat(), content.as_bytes());
+
+        // stops at the first error
+        let mut writes = 0;
+        let result = write_all(content.as_bytes(), |_| {
+            writes += 1;
+            Err("closed")
+        });
+        assert_eq!((result, writes), (Err("closed"), 1));
+
+        write_all(b"", |_| Err("no write for an empty body")).unwrap();
+    }
 }
@@ -0,0 +1,177 @@
+const MAX_ENTITY_LENGTH: usize = 10; // between '&' and ';', such as "#x10FFFF"
+
+/// Escapes the characters that have a special meaning in HTML.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Builds an HTML part from plain text: escaped, with newlines turned into `<br>`.
+pub fn text_to_html(text: &str) -> String {
+    escape(&text.replace("\r\n", "\n")).replace('\n', "<br>\n")
+}
+
+/// Builds a plain text part from HTML, good enough for the text/plain alternative:
+/// line breaks and block ends become newlines, other tags are removed, and so are
+/// scripts and styles with their contents.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            // not a tag, keep the remaining content as is
+            text.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_lowercase();
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if name == "br" || (tag.starts_with('/') && is_block(name)) {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+        if matches!(name, "script" | "style") && !tag.starts_with('/') && !tag.ends_with('/') {
+            // skip the contents up to the closing tag, or to the end if it's missing
+            let closing = format!("</{name}");
+            rest = &rest[find_ignore_case(rest, &closing).unwrap_or(rest.len())..];
+        }
+    }
+    text.push_str(rest);
+
+    unescape(text.trim())
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+    )
+}
+
+/// The position of an ASCII `needle`, whatever the case of `haystack`.
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Decodes common named entities and numeric ones (`&#233;`, `&#xE9;`) in a single pass,
+/// keeping anything else as is.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= MAX_ENTITY_LENGTH)
+            .and_then(|end| Some((decode_entity(&rest[..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => unescaped.push('&'),
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    let code = match entity.strip_prefix('#') {
+        None => {
+            return match entity {
+                "nbsp" => Some(' '),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "amp" => Some('&'),
+                _ => None,
+            }
+        }
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        },
+    };
+    // NUL and surrogates aren't valid characters
+    char::from_u32(code).filter(|c| *c != '\0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_text_to_html() {
+        assert_eq!(
+            text_to_html("Hello <b>\r\nworld\n& bye"),
+            "Hello &lt;b&gt;<br>\nworld<br>\n&amp; bye"
+        );
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text("<p>Hello <b>world</b></p><p>Line 1<br/>Line 2 &amp; more</p>"),
+            "Hello world\nLine 1\nLine 2 & more"
+        );
+    }
+
+    #[test]
+    fn test_html_to_text_unclosed_tag() {
+        assert_eq!(html_to_text("1 < 2"), "1 < 2");
+    }
+
+    #[test]
+    fn test_html_to_text_drops_scripts_and_styles() {
+        assert_eq!(
+            html_to_text(
+                "<style>p { color: red; }</style><p>Hello</p><SCRIPT type=\"text/javascript\">if (a < b) alert('x');</Script>world"
+            ),
+            "Hello\nworld"
+        );
+        assert_eq!(html_to_text("Hello<script>alert('x')"), "Hello");
+        assert_eq!(html_to_text("Hello<script/> world"), "Hello world");
+    }
+
+    #[test]
+    fn test_html_to_text_entities() {
+        assert_eq!(
+            html_to_text("caf&#233; &#xE9;&#XE9; &#39;quoted&apos; &#x2615;"),
+            "café éé 'quoted' ☕"
+        );
+        // decoded once, and invalid entities are kept
+        assert_eq!(
+            html_to_text("&amp;lt; &#0; &#xD800; &#99999999; &unknown; & x;"),
+            "&lt; &#0; &#xD800; &#99999999; &unknown; & x;"
+        );
+    }
+}
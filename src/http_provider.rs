@@ -0,0 +1,188 @@
+//! A generic provider, posting the email as JSON to any HTTP endpoint, such as
+//! a relay in front of another email service. Used for failover.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::email::{base64_encode, Email};
+use crate::email_address::Mailbox;
+use crate::provider::{MailProvider, SendResult};
+use crate::transport::{HttpRequest, Timeouts, Transport};
+
+pub struct HttpProvider<'a> {
+    url: String,
+    api_key: Option<String>,
+    transport: &'a dyn Transport,
+}
+
+#[derive(serde::Serialize)]
+struct HttpPayload {
+    from: Mailbox,
+    to: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cc: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bcc: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reply_to: Vec<Mailbox>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_data: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<HttpPayloadAttachment>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+}
+
+#[derive(serde::Serialize)]
+struct HttpPayloadAttachment {
+    filename: String,
+    content_type: String,
+    content: String, // base64 encoded
+}
+
+impl<'a> HttpProvider<'a> {
+    pub fn new(url: &str, api_key: Option<&str>, transport: &'a dyn Transport) -> Self {
+        Self {
+            url: url.to_string(),
+            api_key: api_key.map(String::from),
+            transport,
+        }
+    }
+}
+
+impl MailProvider for HttpProvider<'_> {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn send(&self, email: &Email, timeouts: Timeouts) -> SendResult {
+        let body = match serde_json::to_vec(&HttpPayload::new(email)) {
+            Ok(body) => body,
+            Err(e) => return SendResult::internal_error(&e.into()),
+        };
+        let mut request = HttpRequest::json(&self.url, body, timeouts);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer(api_key);
+        }
+        match self.transport.post(&request) {
+            Ok(response) => SendResult::from_response(&response),
+            Err(e) => SendResult::transport_error(&e),
+        }
+    }
+}
+
+impl HttpPayload {
+    fn new(email: &Email) -> Self {
+        let (subject, body) = match &email.template_id {
+            Some(_) => (None, None),
+            None => (Some(email.subject.clone()), email.body.as_ref()),
+        };
+        Self {
+            from: email.from.clone(),
+            to: email.recipients.to.clone(),
+            cc: email.recipients.cc.clone(),
+            bcc: email.recipients.bcc.clone(),
+            reply_to: email.reply_to.clone(),
+            subject,
+            text: body.map(|body| body.text.clone()),
+            html: body.map(|body| body.html.clone()),
+            template_id: email.template_id.clone(),
+            template_data: email.template_id.as_ref().and(email.template_data.clone()),
+            attachments: email
+                .attachments
+                .iter()
+                .map(|attachment| HttpPayloadAttachment {
+                    filename: attachment.filename.clone(),
+                    content_type: attachment.content_type.clone(),
+                    content: base64_encode(&attachment.content),
+                })
+                .collect(),
+            headers: email
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::{Attachment, MessageBody, Recipients};
+    use serde_json::json;
+
+    #[test]
+    fn test_build_http_payload() {
+        let mut email = Email::new(
+            "from@example.com".to_string(),
+            Recipients {
+                to: vec!["to@example.com".to_string()],
+                cc: vec!["cc@example.com".to_string()],
+                ..Default::default()
+            },
+            "Hello\r\nBcc: victim@example.com".to_string(),
+            Some(MessageBody::from_text("Hi".to_string())),
+            None,
+            None,
+        );
+        email.set_from_name("John via Website".to_string());
+        email.add_attachment(Attachment {
+            filename: "hello.txt".to_string(),
+            content_type: "text/plain".to_string(),
+            content: b"Hello, world!".to_vec(),
+        });
+        email.add_header("X-Source", "website").unwrap();
+
+        assert_eq!(
+            serde_json::to_value(HttpPayload::new(&email)).unwrap(),
+            json!({
+                "from": {"email": "from@example.com", "name": "John via Website"},
+                "to": ["to@example.com"],
+                "cc": ["cc@example.com"],
+                "subject": "Hello Bcc: victim@example.com",
+                "text": "Hi",
+                "html": "Hi",
+                "attachments": [{
+                    "filename": "hello.txt",
+                    "content_type": "text/plain",
+                    "content": "SGVsbG8sIHdvcmxkIQ==",
+                }],
+                "headers": {"X-Source": "website"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_http_payload_with_template() {
+        let email = Email::new(
+            "from@example.com".to_string(),
+            Recipients {
+                to: vec!["to@example.com".to_string()],
+                ..Default::default()
+            },
+            "Ignored".to_string(),
+            None,
+            Some("d-abc".to_string()),
+            Some(json!({"name": "John"})),
+        );
+        assert_eq!(
+            serde_json::to_value(HttpPayload::new(&email)).unwrap(),
+            json!({
+                "from": {"email": "from@example.com"},
+                "to": ["to@example.com"],
+                "template_id": "d-abc",
+                "template_data": {"name": "John"},
+            })
+        );
+    }
+}
@@ -1,111 +1,742 @@
+mod cors;
+mod email;
+mod email_address;
+mod error;
+mod form;
 mod helpers;
+mod html;
+mod http_provider;
+mod multipart;
+mod provider;
+mod retry;
+mod router;
+mod schema;
 mod sendgrid_payload;
+mod settings;
+mod template;
+mod template_fields;
+mod transport;
 mod world;
 
 use std::collections::HashMap;
+use std::time::Duration;
 
-use sendgrid_payload::SendGridPayload;
+use email::{Attachment, Email, MessageBody, Recipients};
+use email_address::Mailbox;
+use error::{ApiError, ErrorCode};
+use http_provider::HttpProvider;
+use provider::{MailProvider, SendResult};
+use router::Route;
+use sendgrid_payload::SendGrid;
+use settings::{ContentType, InvalidSetting, RecipientMode, Settings};
+use template::Template;
+use transport::{Timeout, Transport, WasiTransport};
 use world::bindings::exports::wasi::http::incoming_handler::Guest;
-use world::bindings::wasi::http::types::IncomingRequest;
 use world::bindings::wasi::http::types::ResponseOutparam;
+use world::bindings::wasi::http::types::{IncomingRequest, Method};
 use world::bindings::Component;
 
-const DEFAULT_SUBJECT: &str = "Contact request";
+const REQUEST_ID_HEADER: &str = "x-request-id";
 
 impl Guest for Component {
     fn handle(req: IncomingRequest, resp: ResponseOutparam) {
-        // check if settings are valid
-        let settings = match Settings::from_req(&req) {
-            Ok(settings) => settings,
-            Err(_) => {
-                let response = helpers::build_response_json_error(
-                    "Failed to parse component settings, missing SendGrid API key",
-                    500,
-                );
-                response.send(resp);
-                return;
-            }
+        let headers = helpers::parse_headers(&IncomingRequest::headers(&req));
+        let request_id = incoming_request_id(&headers).unwrap_or_else(helpers::generate_request_id);
+        // invalid settings fail the request before the body is used
+        let max_body_size = Settings::new(&headers)
+            .map(|settings| settings.max_body_size())
+            .unwrap_or(settings::BODY_HEADROOM);
+        let request = Request {
+            method: req.method(),
+            body: helpers::parse_body(req, &headers, max_body_size),
+            headers,
         };
+        handle_request(&request, &WasiTransport, &request_id).send(resp, &request_id);
+    }
+}
 
-        // read request body
-        let request_body = match helpers::parse_body(req) {
-            Ok(body) => body,
-            Err(e) => {
-                let response = helpers::build_response_json_error(&e, 400);
-                response.send(resp);
-                return;
+/// An incoming request, read from WASI before being handled.
+struct Request {
+    method: Method,
+    headers: HashMap<String, Vec<String>>,
+    /// read errors are only reported when the body is used
+    body: Result<Vec<u8>, ApiError>,
+}
+
+/// Handles a request, with all outgoing calls going through `transport`.
+fn handle_request(
+    request: &Request,
+    transport: &dyn Transport,
+    request_id: &str,
+) -> helpers::ResponseBuilder {
+    let headers = &request.headers;
+    let origin = headers
+        .get("origin")
+        .and_then(|values| values.first())
+        .map(String::as_str);
+
+    // check if settings are valid, still answering preflight requests and
+    // letting allowed origins read the error
+    let settings = match Settings::new(headers) {
+        Ok(settings) => settings,
+        Err(e) => {
+            let allowed_origins = Settings::allowed_origins(headers);
+            let mut response = match request.method {
+                Method::Options => {
+                    preflight_response(origin, &allowed_origins, router::allowed_methods(false))
+                }
+                _ => {
+                    // the details can include a value, so they're only logged
+                    eprintln!("[{request_id}] Invalid component settings: {e}");
+                    let message = match e.downcast_ref::<InvalidSetting>() {
+                        Some(invalid) => format!(
+                            "Invalid component settings, check the '{}' setting",
+                            invalid.key
+                        ),
+                        None => "Invalid component settings".to_string(),
+                    };
+                    let mut error = ApiError::new(ErrorCode::InvalidSettings, message);
+                    error.request_id = request_id.to_string();
+                    helpers::build_response_json_error(&error)
+                }
+            };
+            for (key, value) in cors::headers(origin, &allowed_origins) {
+                response.set_header(key, &value);
             }
-        };
+            response.set_header(REQUEST_ID_HEADER, request_id);
+            return response;
+        }
+    };
 
-        // parse body to JSON
-        let body_json: serde_json::Value = match serde_json::from_slice(&request_body) {
-            Ok(json) => json,
-            Err(_) => {
-                let response =
-                    helpers::build_response_json_error("Invalid JSON in request body", 400);
-                response.send(resp);
-                return;
+    let mut response = match router::route(&request.method, settings.health_check) {
+        Route::SendEmail => {
+            let sendgrid =
+                SendGrid::new(&settings.api_key, settings.sendgrid_base_url(), transport);
+            let failover = settings
+                .failover_url
+                .as_deref()
+                .map(|url| HttpProvider::new(url, settings.failover_api_key.as_deref(), transport));
+            let mut providers: Vec<&dyn MailProvider> = vec![&sendgrid];
+            if let Some(failover) = &failover {
+                providers.push(failover);
             }
-        };
+            send_email(request, &settings, &providers, transport, request_id)
+                .unwrap_or_else(|error| build_error_response(&settings, error, request_id))
+        }
+        // answer CORS preflight requests without sending anything
+        Route::Preflight => preflight_response(
+            origin,
+            &settings.allowed_origins,
+            router::allowed_methods(settings.health_check),
+        ),
+        Route::Health => helpers::build_response_json(&health_document(&settings).to_string(), 200),
+        Route::MethodNotAllowed => {
+            let mut error = ApiError::new(ErrorCode::MethodNotAllowed, "Method not allowed");
+            error.request_id = request_id.to_string();
+            let mut builder = helpers::build_response_json_error(&error);
+            builder.set_header("allow", router::allowed_methods(settings.health_check));
+            builder
+        }
+    };
+
+    for (key, value) in cors::headers(origin, &settings.allowed_origins) {
+        response.set_header(key, &value);
+    }
+    response.set_header(REQUEST_ID_HEADER, request_id);
+    response
+}
+
+fn preflight_response(
+    origin: Option<&str>,
+    allowed_origins: &[String],
+    allowed_methods: &str,
+) -> helpers::ResponseBuilder {
+    let mut builder = helpers::ResponseBuilder::new();
+    builder.set_status_code(204);
+    for (key, value) in cors::preflight_headers(origin, allowed_origins, allowed_methods) {
+        builder.set_header(key, &value);
+    }
+    builder
+}
+
+/// Small document to check that the component is up and configured as expected.
+/// The endpoint is public: only features are listed, never keys, addresses or URLs.
+fn health_document(settings: &Settings) -> serde_json::Value {
+    serde_json::json!({
+        "status": "ok",
+        "component": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "settings": {
+            "recipient_mode": settings.recipient_mode,
+            "content_type": settings.content_type,
+            "template": settings.template_id.is_some(),
+            "acknowledgement": settings.ack_enabled(),
+            "attachments": !settings.attachments_allowed_types.is_empty(),
+            "schema": settings.schema.is_some(),
+            "region": settings.region,
+            "failover": settings.failover_url.is_some(),
+            "retry_max_attempts": settings.retry_max_attempts,
+            "test_mode": settings.test_mode,
+        },
+    })
+}
+
+/// Keeps the request ID set by a proxy or the frontend, if it looks sane, so logs can be correlated.
+fn incoming_request_id(headers: &HashMap<String, Vec<String>>) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|values| values.first())
+        .map(|id| id.trim())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+        .map(String::from)
+}
+
+/// Parses the submission, then sends the notification (and acknowledgement) emails.
+fn send_email(
+    request: &Request,
+    settings: &Settings,
+    providers: &[&dyn MailProvider],
+    transport: &dyn Transport,
+    request_id: &str,
+) -> Result<helpers::ResponseBuilder, ApiError> {
+    let request_body = request.body.as_ref().map_err(Clone::clone)?;
+
+    // parse body to JSON, according to its content type
+    let content_type = request
+        .headers
+        .get("content-type")
+        .and_then(|values| values.first())
+        .map(String::as_str)
+        .unwrap_or_default();
+    let (body_json, attachments) = parse_request_body(content_type, request_body, settings)?;
+
+    // check the submission against the schema from settings, before using any field
+    if let Some(schema) = &settings.schema {
+        let errors = schema.validate(&body_json);
+        if !errors.is_empty() {
+            let message = "Request body doesn't match the schema";
+            return Err(ApiError::new(ErrorCode::ValidationFailed, message).with_errors(errors));
+        }
+    }
+
+    let message = extract_message_body(&body_json, settings)?;
+
+    // only the fields listed in settings are passed to templates, if configured
+    let template_data = match &settings.template_fields {
+        Some(fields) => Some(template_fields::apply(
+            body_json.get("data"),
+            fields,
+            &settings.template_unknown_fields,
+        )?),
+        None => extract_template_data(&body_json, &settings.template_id)?,
+    };
+
+    // extract to/cc/bcc addresses from request body and settings
+    let recipients = extract_recipients(&body_json, settings)?;
+
+    // make sure SendGrid limits are respected before sending anything
+    recipients
+        .validate()
+        .map_err(|e| ApiError::new(ErrorCode::InvalidRecipients, e.to_string()))?;
 
-        let message = match extract_message(&body_json, &settings.template_id) {
-            Ok(data) => data,
-            Err(e) => {
-                let response = helpers::build_response_json_error(&e.to_string(), 400);
-                response.send(resp);
-                return;
+    let reply_to = extract_reply_to(&body_json, settings)?;
+    let subject = extract_subject(&body_json, settings)?;
+
+    // optional sender name, such as "John via Website", or the name given in `email_from`
+    let from_name = match &settings.from_name {
+        Some(from_name) => Some(render_setting(from_name, &body_json, "from_name")?),
+        None => settings.email_from_name.clone(),
+    };
+
+    // build the optional acknowledgement from the same parsed request, which
+    // is skipped rather than preventing the notification if it can't be built
+    let ack_email = build_ack_email(&body_json, settings, &template_data)
+        .map(|ack_email| ack_email.map(Ok))
+        .unwrap_or_else(|e| {
+            eprintln!("[{request_id}] acknowledgement skipped: {e}");
+            Some(Err(()))
+        });
+
+    let mut email = Email::new(
+        settings.email_from.clone(),
+        recipients,
+        subject,
+        message,
+        settings.template_id.clone(),
+        template_data,
+    );
+    email.set_reply_to(reply_to);
+    if let Some(from_name) = from_name {
+        email.set_from_name(from_name);
+    }
+    for attachment in attachments {
+        email.add_attachment(attachment);
+    }
+    for (name, value) in &settings.custom_headers {
+        email.add_header(name, value).map_err(|e| {
+            let message = format!("Invalid 'custom_headers' setting: {e}");
+            ApiError::new(ErrorCode::InvalidSettings, message)
+        })?;
+    }
+
+    let notification = send(
+        providers,
+        transport,
+        &email,
+        settings,
+        "notification",
+        request_id,
+    );
+    if !notification.is_success() {
+        return Err(notification.to_error(settings.debug));
+    }
+
+    // only acknowledge submissions that were actually delivered to the owner
+    let acknowledgement = ack_email.map(|ack_email| match ack_email {
+        Ok(ack_email) => AckOutcome::Sent(send(
+            providers,
+            transport,
+            &ack_email,
+            settings,
+            "acknowledgement",
+            request_id,
+        )),
+        Err(()) => AckOutcome::Skipped {
+            status: DeliveryStatus::Skipped,
+        },
+    });
+
+    // HTML forms are redirected to a page instead of getting a JSON response,
+    // flagged with `ack=failed` or `ack=skipped` if the acknowledgement wasn't sent
+    if let Some(location) = &settings.success_redirect {
+        let location = match &acknowledgement {
+            Some(AckOutcome::Sent(outcome)) if !outcome.is_success() => {
+                eprintln!("[{request_id}] acknowledgement failed, redirecting with ack=failed");
+                append_query_param(location, "ack", "failed")
             }
+            Some(AckOutcome::Skipped { .. }) => append_query_param(location, "ack", "skipped"),
+            _ => location.clone(),
         };
+        return Ok(helpers::build_response_redirect(&location));
+    }
+
+    let body = match acknowledgement {
+        None => serde_json::json!(notification),
+        Some(acknowledgement) => serde_json::json!({
+            "notification": notification,
+            "acknowledgement": acknowledgement,
+        }),
+    };
+    Ok(helpers::build_response_json(&body.to_string(), 202))
+}
+
+/// Builds the JSON error envelope, or a redirect to `error_redirect` carrying the error code if configured.
+fn build_error_response(
+    settings: &Settings,
+    mut error: ApiError,
+    request_id: &str,
+) -> helpers::ResponseBuilder {
+    match &settings.error_redirect {
+        Some(url) => {
+            helpers::build_response_redirect(&append_query_param(url, "error", error.code.as_str()))
+        }
+        None => {
+            error.request_id = request_id.to_string();
+            helpers::build_response_json_error(&error)
+        }
+    }
+}
+
+/// Adds a query parameter to a URL, keeping its existing query and fragment.
+fn append_query_param(url: &str, key: &str, value: &str) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let param = form_urlencoded::Serializer::new(String::new())
+        .append_pair(key, value)
+        .finish();
+
+    match fragment {
+        Some(fragment) => format!("{url}{separator}{param}#{fragment}"),
+        None => format!("{url}{separator}{param}"),
+    }
+}
+
+/// Outcome of sending a message, normalized so the provider's responses never reach visitors as is.
+#[derive(serde::Serialize, Debug)]
+struct SendOutcome {
+    status: DeliveryStatus,
+    /// the provider that made the last attempt, which delivered the message on success
+    provider: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+    /// the provider's status code, or 0 if the request couldn't be sent
+    #[serde(skip)]
+    upstream_status: u16,
+    /// the last attempt didn't complete in time
+    #[serde(skip)]
+    timed_out: bool,
+    /// the email couldn't be prepared for the provider
+    #[serde(skip)]
+    internal: bool,
+    /// the provider's errors (or the transport error), only logged unless in debug mode
+    #[serde(skip)]
+    errors: Vec<serde_json::Value>,
+    /// the provider's status code for each attempt, reported as the number of attempts
+    #[serde(serialize_with = "serialize_count")]
+    attempts: Vec<u16>,
+}
+
+fn serialize_count<S: serde::Serializer>(values: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(values.len() as u64)
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum DeliveryStatus {
+    Queued,
+    Failed,
+    /// not sent at all, only for acknowledgements that couldn't be built
+    Skipped,
+}
+
+/// The acknowledgement's outcome, reported next to the notification's.
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+enum AckOutcome {
+    Sent(SendOutcome),
+    /// the request can't be acknowledged, such as when it has several `email` addresses
+    Skipped {
+        status: DeliveryStatus,
+    },
+}
+
+impl SendOutcome {
+    /// The outcome of the last attempt, with the status codes of all of them.
+    fn new(provider: &'static str, result: SendResult, attempts: Vec<u16>) -> Self {
+        let success = result.is_success();
+        Self {
+            status: match success {
+                true => DeliveryStatus::Queued,
+                false => DeliveryStatus::Failed,
+            },
+            provider,
+            message_id: result.message_id.filter(|_| success),
+            upstream_status: result.status,
+            timed_out: result.timed_out,
+            internal: result.internal,
+            errors: result.errors,
+            attempts,
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        self.status == DeliveryStatus::Queued
+    }
 
-        let template_data = match extract_template_data(&body_json, &settings.template_id) {
-            Ok(data) => data,
-            Err(e) => {
-                let response = helpers::build_response_json_error(&e.to_string(), 400);
-                response.send(resp);
-                return;
+    /// The error reported to the client when the email couldn't be sent,
+    /// with the provider's details only in debug mode.
+    fn to_error(&self, debug: bool) -> ApiError {
+        let mut error = match self.upstream_status {
+            _ if self.internal => {
+                ApiError::new(ErrorCode::InternalError, "The email couldn't be prepared")
             }
+            _ if self.timed_out => ApiError::new(
+                ErrorCode::Timeout,
+                "The email service didn't respond in time",
+            ),
+            429 => ApiError::new(
+                ErrorCode::RateLimited,
+                "Too many emails sent, please try again later",
+            ),
+            _ => ApiError::new(ErrorCode::UpstreamError, "The email couldn't be sent"),
         };
+        if debug {
+            error.details = Some(Box::new(serde_json::json!({
+                "status": self.upstream_status,
+                "errors": self.errors,
+                "attempts": self.attempts,
+            })));
+        }
+        error
+    }
+}
 
-        // extract email from request body
-        let email_to = match body_json.get("email") {
-            Some(value) => value.as_str().unwrap_or("").to_string(), // this removes quotes and converts to String
-            None => {
-                let response = helpers::build_response_json_error(
-                    "Missing 'email' field in request body",
-                    400,
+/// Sends an email, retrying transient failures as configured. Failed attempts are logged.
+/// While a provider is unavailable (5xx or transport errors), the next one is tried, with its own retries.
+/// All attempts, delays included, stay within the `timeout` setting, leaving time for each
+/// failover provider to make an attempt.
+fn send(
+    providers: &[&dyn MailProvider],
+    transport: &dyn Transport,
+    email: &Email,
+    settings: &Settings,
+    label: &str,
+    request_id: &str,
+) -> SendOutcome {
+    let policy = settings.retry_policy();
+    let started = transport.now();
+    let mut attempts = Vec::new();
+    let mut last = None;
+    for (index, provider) in providers.iter().enumerate() {
+        let remaining = (providers.len() - index - 1) as u32;
+        let deadline = started + policy.budget(remaining);
+        if let Some((previous, _)) = last {
+            if policy
+                .timeouts(deadline.saturating_sub(transport.now()))
+                .is_none()
+            {
+                eprintln!(
+                    "[{request_id}] {previous} unavailable, no time left to fail over {label}"
                 );
-                response.send(resp);
-                return;
+                break;
             }
+            eprintln!(
+                "[{request_id}] {previous} unavailable, failing over {label} to {}",
+                provider.name()
+            );
+        }
+        let (result, provider_attempts) = send_with_retries(
+            *provider, transport, email, &policy, deadline, label, request_id,
+        );
+        attempts.extend(provider_attempts);
+        let unavailable = result.is_unavailable();
+        last = Some((provider.name(), result));
+        if !unavailable {
+            break;
+        }
+    }
+
+    let (provider, result) = last.expect("at least one provider");
+    if result.is_success() && provider != providers[0].name() {
+        eprintln!("[{request_id}] {label} delivered by {provider}");
+    }
+    SendOutcome::new(provider, result, attempts)
+}
+
+/// Sends an email through a single provider, returning the last result and the status of each attempt.
+/// Attempts stop at `deadline`, on the transport's clock.
+fn send_with_retries(
+    provider: &dyn MailProvider,
+    transport: &dyn Transport,
+    email: &Email,
+    policy: &retry::RetryPolicy,
+    deadline: Duration,
+    label: &str,
+    request_id: &str,
+) -> (SendResult, Vec<u16>) {
+    let mut attempts = Vec::new();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let Some(timeouts) = policy.timeouts(deadline.saturating_sub(transport.now())) else {
+            return (SendResult::transport_error(&Timeout.into()), attempts);
         };
+        let result = provider.send(email, timeouts);
+        attempts.push(result.status);
+        if result.is_success() {
+            return (result, attempts);
+        }
 
-        // build Slack API payload for simple text message
-        let sendgrid_payload = SendGridPayload::new(
-            settings.email_from,
-            email_to,
-            settings.subject,
-            message,
-            settings.template_id,
-            template_data,
+        eprintln!(
+            "[{request_id}] {} {label} attempt {attempt} failed (status {}): {}",
+            provider.name(),
+            result.status,
+            serde_json::Value::from(result.errors.clone())
         );
+        let retry_after = result.retry_after.as_deref();
+        // give up rather than waking up after the deadline
+        let left = deadline.saturating_sub(transport.now());
+        match policy.delay(attempt, result.status, retry_after) {
+            _ if result.internal => return (result, attempts),
+            Some(delay) if policy.timeouts(left.saturating_sub(delay)).is_some() => {
+                transport.sleep(delay)
+            }
+            _ => return (result, attempts),
+        }
+    }
+}
+
+/// Builds the confirmation sent back to the submitter, if enabled in settings.
+fn build_ack_email(
+    body_json: &serde_json::Value,
+    settings: &Settings,
+    template_data: &Option<serde_json::Value>,
+) -> anyhow::Result<Option<Email>> {
+    if !settings.ack_enabled() {
+        return Ok(None);
+    }
+
+    let submitter = extract_submitter(body_json)?;
+    let subject = match settings.ack_template_id {
+        Some(_) => String::new(), // the template defines its own subject
+        None => render_setting(&settings.ack_subject, body_json, "ack_subject")?,
+    };
+    let mut email = Email::new(
+        settings.email_from.clone(),
+        Recipients {
+            to: vec![submitter],
+            ..Default::default()
+        },
+        subject,
+        settings.ack_message.clone().map(MessageBody::from_text),
+        settings.ack_template_id.clone(),
+        template_data.clone(),
+    );
+    // goes to any address given by the visitor, so the sender name never comes from the request
+    if let Some(name) = &settings.email_from_name {
+        email.set_from_name(name.clone());
+    }
+    Ok(Some(email))
+}
+
+/// Parses a JSON, url-encoded or multipart/form-data request body into JSON.
+/// File parts of multipart bodies are returned as attachments.
+fn parse_request_body(
+    content_type: &str,
+    body: &[u8],
+    settings: &Settings,
+) -> anyhow::Result<(serde_json::Value, Vec<Attachment>)> {
+    if let Some(boundary) = multipart::boundary(content_type) {
+        let mut fields = Vec::new();
+        let mut files = Vec::new();
+        for part in multipart::parse(body, &boundary).map_err(invalid_body)? {
+            if part.is_file() {
+                files.push(part);
+            } else {
+                let value = String::from_utf8(part.data).map_err(|_| {
+                    let message = format!("Invalid UTF-8 in '{}' field of request body", part.name);
+                    ApiError::new(ErrorCode::InvalidBody, message).with_field(&part.name)
+                })?;
+                fields.push((part.name, value));
+            }
+        }
+        let attachments = extract_attachments(files, settings)?;
+        return Ok((
+            form::fields_to_json(fields).map_err(invalid_body)?,
+            attachments,
+        ));
+    }
+
+    if form::is_urlencoded(content_type) {
+        return Ok((form::parse_urlencoded(body).map_err(invalid_body)?, vec![]));
+    }
+
+    let json = serde_json::from_slice(body)
+        .map_err(|_| ApiError::new(ErrorCode::InvalidBody, "Invalid JSON in request body"))?;
+    Ok((json, vec![]))
+}
+
+fn invalid_body(error: anyhow::Error) -> ApiError {
+    ApiError::new(ErrorCode::InvalidBody, error.to_string())
+}
+
+/// Checks uploaded files against the attachment settings.
+fn extract_attachments(
+    files: Vec<multipart::Part>,
+    settings: &Settings,
+) -> anyhow::Result<Vec<Attachment>> {
+    let mut attachments = Vec::new();
+    let mut total_size = 0;
+    for file in files {
+        let filename = file.filename.unwrap_or_default();
+        let invalid = |message: String| {
+            ApiError::new(ErrorCode::InvalidAttachment, message).with_field(&file.name)
+        };
+        // browsers send an empty file part when no file is selected
+        if filename.is_empty() && file.data.is_empty() {
+            continue;
+        }
 
-        let sendgrid_response = sendgrid_payload.send(&settings.api_key);
+        let content_type = file
+            .content_type
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        if !is_type_allowed(&content_type, &settings.attachments_allowed_types) {
+            return Err(invalid(format!(
+                "Attachment '{filename}' has a type that is not allowed ({content_type})"
+            ))
+            .into());
+        }
 
-        // handle error in case request couldn't be sent
-        if let Err(e) = sendgrid_response {
-            let response = helpers::build_response_json_error(&e.to_string(), 500);
-            response.send(resp);
-            return;
+        if file.data.len() > settings.attachments_max_file_size {
+            return Err(invalid(format!(
+                "Attachment '{filename}' exceeds the maximum size of {} bytes",
+                settings.attachments_max_file_size
+            ))
+            .into());
+        }
+        total_size += file.data.len();
+        if total_size > settings.attachments_max_total_size {
+            return Err(invalid(format!(
+                "Attachments exceed the maximum total size of {} bytes",
+                settings.attachments_max_total_size
+            ))
+            .into());
         }
 
-        let sendgrid_response = sendgrid_response.unwrap();
-        let response_status = sendgrid_response.status_code();
-        let response_body =
-            String::from_utf8_lossy(&sendgrid_response.body().unwrap_or_default()).to_string();
+        attachments.push(Attachment {
+            filename,
+            content_type,
+            content: file.data,
+        });
+    }
+    Ok(attachments)
+}
+
+/// Matches a MIME type against allowed types such as `application/pdf` or `image/*`.
+fn is_type_allowed(content_type: &str, allowed_types: &[String]) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    allowed_types.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        match allowed.strip_suffix("/*") {
+            Some(prefix) => mime.split('/').next() == Some(prefix),
+            None => mime == allowed,
+        }
+    })
+}
 
-        let response = helpers::build_response_json(&response_body, response_status);
-        response.send(resp);
+/// The static content: rendered from the body templates if configured,
+/// otherwise from the `message` and `html` fields.
+fn extract_message_body(
+    body_json: &serde_json::Value,
+    settings: &Settings,
+) -> anyhow::Result<Option<MessageBody>> {
+    if settings.template_id.is_none()
+        && (settings.body_template.is_some() || settings.body_template_html.is_some())
+    {
+        let text = settings
+            .body_template
+            .as_ref()
+            .map(|template| render_setting(template, body_json, "body_template"))
+            .transpose()?;
+        let html = settings
+            .body_template_html
+            .as_ref()
+            .map(|template| {
+                template
+                    .render_html(body_json)
+                    .map_err(|e| setting_error(e, "body_template_html"))
+            })
+            .transpose()?;
+        return Ok(build_message_body(text, html, &ContentType::Text));
     }
+
+    let message = extract_message(body_json, &settings.template_id)?;
+    let html = extract_html(body_json)?;
+    Ok(build_message_body(message, html, &settings.content_type))
 }
 
 fn extract_message(
@@ -113,19 +744,44 @@ fn extract_message(
     template_id: &Option<String>,
 ) -> anyhow::Result<Option<String>> {
     match body_json.get("message") {
-        // just return the value if it exists (removing quotes and converting to String)
-        Some(value) => Ok(Some(value.as_str().unwrap_or("").to_string())),
+        Some(serde_json::Value::String(message)) => Ok(Some(message.to_string())),
+        Some(_) => Err(invalid_field("message", "'message' field must be a string")),
         None => {
-            // if template_id is provided, message is not required
-            if template_id.is_some() && !template_id.as_ref().unwrap().is_empty() {
+            // if template_id or an html body is provided, message is not required
+            if template_id.is_some() || body_json.get("html").is_some() {
                 Ok(None)
             } else {
-                Err(anyhow::anyhow!("Missing 'message' field in request body"))
+                Err(missing_field("message"))
             }
         }
     }
 }
 
+fn extract_html(body_json: &serde_json::Value) -> anyhow::Result<Option<String>> {
+    match body_json.get("html") {
+        Some(serde_json::Value::String(html)) => Ok(Some(html.to_string())),
+        Some(_) => Err(invalid_field("html", "'html' field must be a string")),
+        None => Ok(None),
+    }
+}
+
+/// Combines the plain text and HTML bodies, generating the missing part when needed.
+fn build_message_body(
+    message: Option<String>,
+    html: Option<String>,
+    content_type: &ContentType,
+) -> Option<MessageBody> {
+    match (message, html) {
+        (Some(text), Some(html)) => Some(MessageBody { text, html }),
+        (None, Some(html)) => Some(MessageBody::from_html(html)),
+        (Some(message), None) => match content_type {
+            ContentType::Text => Some(MessageBody::from_text(message)),
+            ContentType::Html => Some(MessageBody::from_html(message)),
+        },
+        (None, None) => None,
+    }
+}
+
 fn extract_template_data(
     body_json: &serde_json::Value,
     template_id: &Option<String>,
@@ -135,124 +791,170 @@ fn extract_template_data(
         Some(value) => Ok(Some(value.clone())),
         None => {
             // if template_id is not provided, data is not required
-            if template_id.is_none() || template_id.as_ref().unwrap().is_empty() {
+            if template_id.is_none() {
                 Ok(None)
             } else {
-                Err(anyhow::anyhow!("Missing 'data' field in request body"))
+                Err(missing_field("data"))
             }
         }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
-pub struct Settings {
-    pub api_key: String,
-    pub email_from: String,
-    pub subject: String,             // optional, defaults to "Contact request"
-    pub template_id: Option<String>, // optional
-}
-
-impl Settings {
-    pub fn from_req(req: &IncomingRequest) -> anyhow::Result<Self> {
-        let map = helpers::parse_headers(&IncomingRequest::headers(req));
-        Self::new(&map)
+fn extract_recipients(
+    body_json: &serde_json::Value,
+    settings: &Settings,
+) -> anyhow::Result<Recipients> {
+    // in fixed mode, recipients only come from settings so the endpoint can't be used as an open relay
+    if settings.recipient_mode == RecipientMode::Fixed {
+        return Ok(Recipients {
+            to: settings.email_to.clone(),
+            cc: settings.cc.clone(),
+            bcc: settings.bcc.clone(),
+        });
     }
 
-    pub fn new(headers: &HashMap<String, Vec<String>>) -> anyhow::Result<Self> {
-        let settings = headers
-            .get("x-edgee-component-settings")
-            .ok_or_else(|| anyhow::anyhow!("Missing 'x-edgee-component-settings' header"))?;
+    let to = match body_json.get("email") {
+        Some(value) => extract_addresses(value, "email")?,
+        None => return Err(missing_field("email")),
+    };
 
-        if settings.len() != 1 {
-            return Err(anyhow::anyhow!(
-                "Expected exactly one 'x-edgee-component-settings' header, found {}",
-                settings.len()
-            ));
-        }
-        let setting = settings[0].clone();
-        let setting: HashMap<String, String> = serde_json::from_str(&setting)?;
+    // cc and bcc are optional in the request body, and merged with the ones from settings
+    let mut cc = settings.cc.clone();
+    let mut bcc = settings.bcc.clone();
+    if let Some(value) = body_json.get("cc") {
+        merge_addresses(&mut cc, extract_addresses(value, "cc")?);
+    }
+    if let Some(value) = body_json.get("bcc") {
+        merge_addresses(&mut bcc, extract_addresses(value, "bcc")?);
+    }
 
-        let api_key = setting
-            .get("api_key")
-            .map(String::to_string)
-            .unwrap_or_default();
+    Ok(Recipients { to, cc, bcc })
+}
 
-        let email_from = setting
-            .get("email_from")
-            .map(String::to_string)
-            .unwrap_or_default();
+/// Reply-to addresses from settings. Otherwise in fixed mode,
+/// the visitor's address is used as reply-to instead of recipient.
+fn extract_reply_to(
+    body_json: &serde_json::Value,
+    settings: &Settings,
+) -> anyhow::Result<Vec<Mailbox>> {
+    if !settings.reply_to.is_empty() {
+        return Ok(settings.reply_to.clone());
+    }
+    if settings.recipient_mode != RecipientMode::Fixed {
+        return Ok(vec![]);
+    }
 
-        let subject = setting
-            .get("subject")
-            .map(String::to_string)
-            .unwrap_or(DEFAULT_SUBJECT.to_string());
+    let email = extract_submitter(body_json)?;
+    let name = settings
+        .reply_to_name
+        .as_ref()
+        .map(|name| render_setting(name, body_json, "reply_to_name"))
+        .transpose()?;
+    Ok(vec![Mailbox { email, name }])
+}
 
-        let template_id: Option<String> = setting.get("template_id").cloned();
+/// The subject from the request body if allowed in settings, or the (templated) subject setting.
+/// Unused with dynamic templates, which define their own subject.
+fn extract_subject(body_json: &serde_json::Value, settings: &Settings) -> anyhow::Result<String> {
+    if settings.template_id.is_some() {
+        return Ok(String::new());
+    }
 
-        Ok(Self {
-            api_key,
-            email_from,
-            subject,
-            template_id,
-        })
+    if settings.allow_subject_override {
+        match body_json.get("subject") {
+            Some(serde_json::Value::String(subject)) if !subject.trim().is_empty() => {
+                return Ok(subject.to_string())
+            }
+            Some(serde_json::Value::String(_)) | Some(serde_json::Value::Null) | None => {}
+            Some(_) => return Err(invalid_field("subject", "'subject' field must be a string")),
+        }
     }
+
+    render_setting(&settings.subject, body_json, "subject")
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Renders a templated setting with the request fields.
+fn render_setting(
+    template: &Template,
+    body_json: &serde_json::Value,
+    key: &str,
+) -> anyhow::Result<String> {
+    template
+        .render(body_json)
+        .map_err(|e| setting_error(e, key))
+}
 
-    #[test]
-    fn test_settings_new() {
-        let mut headers = HashMap::new();
-        headers.insert(
-            "x-edgee-component-settings".to_string(),
-            vec![r#"{"api_key": "test_value"}"#.to_string()],
-        );
+/// Names the setting in errors about the request fields it uses, keeping their code.
+fn setting_error(error: anyhow::Error, key: &str) -> anyhow::Error {
+    let mut error = ApiError::from(error);
+    error.message = format!("{}, used in '{key}' setting", error.message);
+    error.into()
+}
 
-        let settings = Settings::new(&headers).unwrap();
-        assert_eq!(settings.api_key, "test_value");
+/// The visitor's own address, as a single string.
+fn extract_submitter(body_json: &serde_json::Value) -> anyhow::Result<String> {
+    match body_json.get("email") {
+        Some(serde_json::Value::String(email)) => validate_address(email, "email"),
+        Some(_) => Err(invalid_field("email", "'email' field must be a string")),
+        None => Err(missing_field("email")),
     }
+}
 
-    #[test]
-    fn test_settings_new_missing_header() {
-        let headers = HashMap::new();
-        let result = Settings::new(&headers);
-        assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().to_string(),
-            "Missing 'x-edgee-component-settings' header"
-        );
+/// Accepts either a single address or an array of addresses.
+fn extract_addresses(value: &serde_json::Value, field: &str) -> anyhow::Result<Vec<String>> {
+    let invalid = || {
+        let message = format!("'{field}' field must be a string or an array of strings");
+        invalid_field(field, &message)
+    };
+    match value {
+        serde_json::Value::String(email) => Ok(vec![validate_address(email, field)?]),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                serde_json::Value::String(email) => validate_address(email, field),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        _ => Err(invalid()),
     }
+}
 
-    #[test]
-    fn test_settings_new_multiple_headers() {
-        let mut headers = HashMap::new();
-        headers.insert(
-            "x-edgee-component-settings".to_string(),
-            vec![
-                r#"{"api_key": "test_value"}"#.to_string(),
-                r#"{"api_key": "another_value"}"#.to_string(),
-            ],
-        );
-        let result = Settings::new(&headers);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("Expected exactly one 'x-edgee-component-settings' header"));
-    }
+/// Validates an address from the request body, so invalid ones are rejected before calling SendGrid.
+fn validate_address(email: &str, field: &str) -> anyhow::Result<String> {
+    email_address::parse(email).map_err(|e| {
+        let message = format!("Invalid email address '{email}' in '{field}' field: {e}");
+        ApiError::new(ErrorCode::InvalidEmail, message)
+            .with_field(field)
+            .into()
+    })
+}
 
-    #[test]
-    fn test_settings_new_invalid_json() {
-        let mut headers = HashMap::new();
-        headers.insert(
-            "x-edgee-component-settings".to_string(),
-            vec!["not a json".to_string()],
-        );
-        let result = Settings::new(&headers);
-        assert!(result.is_err());
+fn missing_field(field: &str) -> anyhow::Error {
+    let message = format!("Missing '{field}' field in request body");
+    ApiError::new(ErrorCode::MissingField, message)
+        .with_field(field)
+        .into()
+}
+
+fn invalid_field(field: &str, message: &str) -> anyhow::Error {
+    ApiError::new(ErrorCode::InvalidField, message)
+        .with_field(field)
+        .into()
+}
+
+/// Appends addresses that are not already present (case-insensitive).
+fn merge_addresses(addresses: &mut Vec<String>, others: Vec<String>) {
+    for email in others {
+        if !addresses.iter().any(|e| e.eq_ignore_ascii_case(&email)) {
+            addresses.push(email);
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::{HttpRequest, HttpResponse};
 
     #[test]
     fn test_extract_message_with_message() {
@@ -265,11 +967,29 @@ mod tests {
     #[test]
     fn test_extract_message_with_message_empty_template_id() {
         let json = serde_json::json!({"message": "Hello, world!"});
-        let template_id = Some("".to_string()); //empty string instead of None
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "template_id": ""}"#,
+        );
+        let template_id = settings.template_id; // empty string normalized to None
         let result = extract_message(&json, &template_id).unwrap();
         assert_eq!(result, Some("Hello, world!".to_string()));
     }
 
+    #[test]
+    fn test_extract_message_not_a_string() {
+        for message in [
+            serde_json::json!(42),
+            serde_json::json!({"text": "Hello"}),
+            serde_json::Value::Null,
+        ] {
+            let json = serde_json::json!({"message": message});
+            let error = ApiError::from(extract_message(&json, &None).unwrap_err());
+            assert_eq!((error.code, error.status), (ErrorCode::InvalidField, 400));
+            assert_eq!(error.field.as_deref(), Some("message"));
+            assert_eq!(error.message, "'message' field must be a string");
+        }
+    }
+
     #[test]
     fn test_extract_message_missing_message_with_template_id() {
         let json = serde_json::json!({});
@@ -293,7 +1013,10 @@ mod tests {
     #[test]
     fn test_extract_message_missing_message_with_empty_template_id() {
         let json = serde_json::json!({});
-        let template_id = Some("".to_string()); //empty string instead of None
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "template_id": ""}"#,
+        );
+        let template_id = settings.template_id; // empty string normalized to None
         let result = extract_message(&json, &template_id);
         assert!(result.is_err());
         assert_eq!(
@@ -303,30 +1026,1460 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_template_data_with_data() {
-        let json = serde_json::json!({"data": {"foo": "bar"}});
-        let template_id = Some("template123".to_string());
-        let result = extract_template_data(&json, &template_id).unwrap();
-        assert_eq!(result, Some(serde_json::json!({"foo": "bar"})));
+    fn test_extract_message_missing_message_with_html() {
+        let json = serde_json::json!({"html": "<p>Hello</p>"});
+        let result = extract_message(&json, &None).unwrap();
+        assert_eq!(result, None);
     }
 
     #[test]
-    fn test_extract_template_data_missing_data_with_template_id() {
-        let json = serde_json::json!({});
-        let template_id = Some("template123".to_string());
-        let result = extract_template_data(&json, &template_id);
-        assert!(result.is_err());
+    fn test_extract_html() {
+        let json = serde_json::json!({"html": "<p>Hello</p>"});
+        assert_eq!(
+            extract_html(&json).unwrap(),
+            Some("<p>Hello</p>".to_string())
+        );
+        assert_eq!(extract_html(&serde_json::json!({})).unwrap(), None);
+        assert_eq!(
+            extract_html(&serde_json::json!({"html": 1}))
+                .unwrap_err()
+                .to_string(),
+            "'html' field must be a string"
+        );
+    }
+
+    #[test]
+    fn test_build_message_body() {
+        let text = Some("Hello\nworld".to_string());
+        let html = Some("<p>Hello</p>".to_string());
+
+        // both parts provided
+        assert_eq!(
+            build_message_body(text.clone(), html.clone(), &ContentType::Text),
+            Some(MessageBody {
+                text: "Hello\nworld".to_string(),
+                html: "<p>Hello</p>".to_string(),
+            })
+        );
+
+        // html only
+        assert_eq!(
+            build_message_body(None, html, &ContentType::Text),
+            Some(MessageBody {
+                text: "Hello".to_string(),
+                html: "<p>Hello</p>".to_string(),
+            })
+        );
+
+        // plain text message, html generated
+        assert_eq!(
+            build_message_body(text, None, &ContentType::Text),
+            Some(MessageBody {
+                text: "Hello\nworld".to_string(),
+                html: "Hello<br>\nworld".to_string(),
+            })
+        );
+
+        // html message with content_type setting, text generated
+        assert_eq!(
+            build_message_body(Some("<b>Hi</b>".to_string()), None, &ContentType::Html),
+            Some(MessageBody {
+                text: "Hi".to_string(),
+                html: "<b>Hi</b>".to_string(),
+            })
+        );
+
+        assert_eq!(build_message_body(None, None, &ContentType::Text), None);
+    }
+
+    const MULTIPART_BODY: &str = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"email\"\r\n\
+        \r\n\
+        test@example.com\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"message\"\r\n\
+        \r\n\
+        Hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"cv\"; filename=\"cv.pdf\"\r\n\
+        Content-Type: application/pdf\r\n\
+        \r\n\
+        %PDF-1.4\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"screenshot\"; filename=\"\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn test_parse_request_body_json() {
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let (json, attachments) =
+            parse_request_body("", br#"{"email": "test@example.com"}"#, &settings).unwrap();
+        assert_eq!(json, serde_json::json!({"email": "test@example.com"}));
+        assert!(attachments.is_empty());
+
+        let result = parse_request_body("application/json", b"not json", &settings);
         assert_eq!(
             result.unwrap_err().to_string(),
-            "Missing 'data' field in request body"
+            "Invalid JSON in request body"
         );
     }
 
     #[test]
-    fn test_extract_template_data_missing_data_without_template_id() {
-        let json = serde_json::json!({});
-        let template_id = None;
-        let result = extract_template_data(&json, &template_id).unwrap();
-        assert_eq!(result, None);
+    fn test_parse_request_body_urlencoded() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "template_id": "d-123"}"#,
+        );
+        let (json, attachments) = parse_request_body(
+            "application/x-www-form-urlencoded",
+            b"email=test%40example.com&data%5Bname%5D=John&data%5Btopic%5D=Sales",
+            &settings,
+        )
+        .unwrap();
+        assert!(attachments.is_empty());
+        assert_eq!(
+            extract_template_data(&json, &settings.template_id).unwrap(),
+            Some(serde_json::json!({"name": "John", "topic": "Sales"}))
+        );
+        assert_eq!(
+            extract_recipients(&json, &settings).unwrap().to,
+            vec!["test@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_parse_request_body_multipart() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "attachments_allowed_types": "image/*, application/pdf"}"#,
+        );
+        let (json, attachments) = parse_request_body(
+            "multipart/form-data; boundary=XyZ",
+            MULTIPART_BODY.as_bytes(),
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"email": "test@example.com", "message": "Hello"})
+        );
+        assert_eq!(
+            attachments,
+            vec![Attachment {
+                filename: "cv.pdf".to_string(),
+                content_type: "application/pdf".to_string(),
+                content: b"%PDF-1.4".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_request_body_multipart_type_not_allowed() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "attachments_allowed_types": "image/*"}"#,
+        );
+        let result = parse_request_body(
+            "multipart/form-data; boundary=XyZ",
+            MULTIPART_BODY.as_bytes(),
+            &settings,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attachment 'cv.pdf' has a type that is not allowed (application/pdf)"
+        );
+    }
+
+    #[test]
+    fn test_parse_request_body_multipart_too_large() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "attachments_allowed_types": "application/pdf", "attachments_max_file_size": "4"}"#,
+        );
+        let result = parse_request_body(
+            "multipart/form-data; boundary=XyZ",
+            MULTIPART_BODY.as_bytes(),
+            &settings,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attachment 'cv.pdf' exceeds the maximum size of 4 bytes"
+        );
+
+        // the maximum file size defaults to the maximum total size
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "attachments_allowed_types": "application/pdf", "attachments_max_total_size": "4"}"#,
+        );
+        let result = parse_request_body(
+            "multipart/form-data; boundary=XyZ",
+            MULTIPART_BODY.as_bytes(),
+            &settings,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attachment 'cv.pdf' exceeds the maximum size of 4 bytes"
+        );
+    }
+
+    #[test]
+    fn test_extract_attachments_total_size() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "attachments_allowed_types": "text/plain", "attachments_max_file_size": "4", "attachments_max_total_size": "6"}"#,
+        );
+        let file = |name: &str| multipart::Part {
+            name: "files".to_string(),
+            filename: Some(name.to_string()),
+            content_type: Some("text/plain; charset=utf-8".to_string()),
+            data: b"1234".to_vec(),
+        };
+        let result = extract_attachments(vec![file("a.txt"), file("b.txt")], &settings);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Attachments exceed the maximum total size of 6 bytes"
+        );
+    }
+
+    #[test]
+    fn test_is_type_allowed() {
+        let allowed = vec!["image/*".to_string(), "application/PDF".to_string()];
+        assert!(is_type_allowed("image/png", &allowed));
+        assert!(is_type_allowed("application/pdf; name=cv.pdf", &allowed));
+        assert!(!is_type_allowed("imagex/png", &allowed));
+        assert!(!is_type_allowed("application/zip", &allowed));
+        assert!(!is_type_allowed("image/png", &[]));
+    }
+
+    #[test]
+    fn test_health_document_hides_configuration() {
+        let settings = settings_with(
+            r#"{"api_key": "SG.secret", "email_from": "from@example.com", "health_check": "true", "recipient_mode": "fixed", "email_to": "team@example.com", "bcc": "archive@example.com", "api_base_url": "https://sendgrid.internal.example.com", "failover_url": "https://relay.internal.example.com/send", "failover_api_key": "relay-secret"}"#,
+        );
+        let document = health_document(&settings);
+        assert_eq!(document["status"], "ok");
+        assert_eq!(document["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(document["settings"]["recipient_mode"], "fixed");
+        assert_eq!(document["settings"]["failover"], true);
+        let document = document.to_string();
+        for secret in [
+            "SG.secret",
+            "from@example.com",
+            "team@example.com",
+            "archive@example.com",
+            "sendgrid.internal.example.com",
+            "relay.internal.example.com",
+            "relay-secret",
+        ] {
+            assert!(!document.contains(secret), "{secret} in {document}");
+        }
+    }
+
+    #[test]
+    fn test_append_query_param() {
+        assert_eq!(
+            append_query_param("/contact", "error", "invalid_request"),
+            "/contact?error=invalid_request"
+        );
+        assert_eq!(
+            append_query_param(
+                "https://example.com/contact?lang=en#form",
+                "error",
+                "send_failed"
+            ),
+            "https://example.com/contact?lang=en&error=send_failed#form"
+        );
+        assert_eq!(
+            append_query_param("/contact", "error", "a b&c"),
+            "/contact?error=a+b%26c"
+        );
+    }
+
+    #[test]
+    fn test_extract_message_body_default_template() {
+        let json = serde_json::json!({
+            "name": "John <john@example.com>",
+            "message": "Hello\nworld",
+            "data": {"company": "ACME", "tags": ["a", "b"]},
+        });
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "body_template": "default"}"#,
+        );
+        let message = extract_message_body(&json, &settings).unwrap().unwrap();
+        assert_eq!(
+            message.text,
+            "name: John <john@example.com>\nmessage: Hello\nworld\ndata: company: ACME, tags: a, b\n"
+        );
+        assert_eq!(
+            message.html,
+            "<table cellpadding=\"4\">\
+            <tr><th align=\"left\" valign=\"top\">name</th><td style=\"white-space: pre-wrap\">John &lt;john@example.com&gt;</td></tr>\
+            <tr><th align=\"left\" valign=\"top\">message</th><td style=\"white-space: pre-wrap\">Hello\nworld</td></tr>\
+            <tr><th align=\"left\" valign=\"top\">data</th><td style=\"white-space: pre-wrap\">company: ACME, tags: a, b</td></tr>\
+            </table>"
+        );
+    }
+
+    #[test]
+    fn test_extract_message_body_custom_template() {
+        let json = serde_json::json!({"name": "<b>John</b>", "topics": ["pricing", "support"]});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "body_template_html": "<p>From {{name}}</p><ul>{{#each topics}}<li>{{this}}</li>{{/each}}</ul>"}"#,
+        );
+        let message = extract_message_body(&json, &settings).unwrap().unwrap();
+        assert_eq!(
+            message.html,
+            "<p>From &lt;b&gt;John&lt;/b&gt;</p><ul><li>pricing</li><li>support</li></ul>"
+        );
+        assert_eq!(message.text, "From <b>John</b>\npricing\nsupport");
+
+        assert_eq!(
+            extract_message_body(&serde_json::json!({}), &settings)
+                .unwrap_err()
+                .to_string(),
+            "Missing 'name' field in request body, used in 'body_template_html' setting"
+        );
+
+        // without body template, the message field is required
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        assert_eq!(
+            extract_message_body(&json, &settings)
+                .unwrap_err()
+                .to_string(),
+            "Missing 'message' field in request body"
+        );
+    }
+
+    #[test]
+    fn test_extract_template_data_with_data() {
+        let json = serde_json::json!({"data": {"foo": "bar"}});
+        let template_id = Some("template123".to_string());
+        let result = extract_template_data(&json, &template_id).unwrap();
+        assert_eq!(result, Some(serde_json::json!({"foo": "bar"})));
+    }
+
+    #[test]
+    fn test_extract_template_data_missing_data_with_template_id() {
+        let json = serde_json::json!({});
+        let template_id = Some("template123".to_string());
+        let result = extract_template_data(&json, &template_id);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing 'data' field in request body"
+        );
+    }
+
+    #[test]
+    fn test_extract_template_data_missing_data_without_template_id() {
+        let json = serde_json::json!({});
+        let template_id = None;
+        let result = extract_template_data(&json, &template_id).unwrap();
+        assert_eq!(result, None);
+    }
+
+    fn settings_with(settings: &str) -> Settings {
+        Settings::from_json(settings).unwrap()
+    }
+
+    #[test]
+    fn test_extract_recipients_single_email() {
+        let json = serde_json::json!({"email": "to@example.com"});
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let result = extract_recipients(&json, &settings).unwrap();
+        assert_eq!(result.to, vec!["to@example.com"]);
+        assert!(result.cc.is_empty());
+        assert!(result.bcc.is_empty());
+    }
+
+    #[test]
+    fn test_extract_recipients_arrays_merged_with_settings() {
+        let json = serde_json::json!({
+            "email": ["a@example.com", "b@example.com"],
+            "cc": "c@example.com",
+            "bcc": ["d@example.com", "E@example.com"],
+        });
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "cc": "x@example.com", "bcc": "e@example.com"}"#,
+        );
+        let result = extract_recipients(&json, &settings).unwrap();
+        assert_eq!(result.to, vec!["a@example.com", "b@example.com"]);
+        assert_eq!(result.cc, vec!["x@example.com", "c@example.com"]);
+        assert_eq!(result.bcc, vec!["e@example.com", "d@example.com"]);
+    }
+
+    #[test]
+    fn test_extract_recipients_missing_email() {
+        let json = serde_json::json!({"cc": "c@example.com"});
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let result = extract_recipients(&json, &settings);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing 'email' field in request body"
+        );
+    }
+
+    #[test]
+    fn test_extract_recipients_fixed_mode_ignores_body() {
+        let json = serde_json::json!({
+            "email": "visitor@example.com",
+            "cc": "someone@example.com",
+            "bcc": "else@example.com",
+        });
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "recipient_mode": "fixed", "email_to": "owner@example.com", "bcc": "archive@example.com"}"#,
+        );
+        let result = extract_recipients(&json, &settings).unwrap();
+        assert_eq!(result.to, vec!["owner@example.com"]);
+        assert!(result.cc.is_empty());
+        assert_eq!(result.bcc, vec!["archive@example.com"]);
+    }
+
+    #[test]
+    fn test_extract_reply_to() {
+        let json = serde_json::json!({"email": "visitor@example.com", "name": "John"});
+        let visitor =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        assert_eq!(extract_reply_to(&json, &visitor).unwrap(), vec![]);
+
+        let fixed = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "recipient_mode": "fixed", "email_to": "owner@example.com"}"#,
+        );
+        assert_eq!(
+            extract_reply_to(&json, &fixed).unwrap(),
+            vec![Mailbox {
+                email: "visitor@example.com".to_string(),
+                name: None,
+            }]
+        );
+
+        let named = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "recipient_mode": "fixed", "email_to": "owner@example.com", "reply_to_name": "{{name}} (website)"}"#,
+        );
+        assert_eq!(
+            extract_reply_to(&json, &named).unwrap(),
+            vec![Mailbox {
+                email: "visitor@example.com".to_string(),
+                name: Some("John (website)".to_string()),
+            }]
+        );
+        assert_eq!(
+            extract_reply_to(&serde_json::json!({"email": "visitor@example.com"}), &named)
+                .unwrap_err()
+                .to_string(),
+            "Missing 'name' field in request body, used in 'reply_to_name' setting"
+        );
+
+        let configured = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "recipient_mode": "fixed", "email_to": "owner@example.com", "reply_to": "Support <support@example.com>"}"#,
+        );
+        assert_eq!(
+            extract_reply_to(&json, &configured).unwrap(),
+            vec![Mailbox {
+                email: "support@example.com".to_string(),
+                name: Some("Support".to_string()),
+            }]
+        );
+
+        let json = serde_json::json!({"email": ["a@example.com", "b@example.com"]});
+        assert_eq!(
+            extract_reply_to(&json, &fixed).unwrap_err().to_string(),
+            "'email' field must be a string"
+        );
+    }
+
+    #[test]
+    fn test_extract_subject() {
+        let json =
+            serde_json::json!({"name": "John", "data": {"topic": "pricing"}, "subject": "Custom"});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "subject": "New contact from {{name}} about {{data.topic}}"}"#,
+        );
+        assert_eq!(
+            extract_subject(&json, &settings).unwrap(),
+            "New contact from John about pricing"
+        );
+        assert_eq!(
+            extract_subject(&serde_json::json!({"name": "John"}), &settings)
+                .unwrap_err()
+                .to_string(),
+            "Missing 'data.topic' field in request body, used in 'subject' setting"
+        );
+
+        // the template defines the subject
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "subject": "{{missing}}", "template_id": "d-123"}"#,
+        );
+        assert_eq!(extract_subject(&json, &settings).unwrap(), "");
+    }
+
+    #[test]
+    fn test_extract_subject_override() {
+        let json = serde_json::json!({"subject": "Custom"});
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        assert_eq!(
+            extract_subject(&json, &settings).unwrap(),
+            "Contact request"
+        );
+
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "allow_subject_override": true}"#,
+        );
+        assert_eq!(extract_subject(&json, &settings).unwrap(), "Custom");
+        assert_eq!(
+            extract_subject(&serde_json::json!({"subject": ""}), &settings).unwrap(),
+            "Contact request"
+        );
+        assert_eq!(
+            extract_subject(&serde_json::json!({"subject": 42}), &settings)
+                .unwrap_err()
+                .to_string(),
+            "'subject' field must be a string"
+        );
+    }
+
+    #[test]
+    fn test_build_ack_email_disabled() {
+        let json = serde_json::json!({"email": "visitor@example.com"});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_template_id": ""}"#,
+        );
+        assert!(!settings.ack_enabled());
+        assert!(build_ack_email(&json, &settings, &None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_build_ack_email_with_message() {
+        let json = serde_json::json!({"email": "visitor@example.com", "message": "Hi"});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_message": "Thanks!"}"#,
+        );
+        let email = build_ack_email(&json, &settings, &None).unwrap().unwrap();
+        assert_eq!(
+            email,
+            Email::new(
+                "from@example.com".to_string(),
+                Recipients {
+                    to: vec!["visitor@example.com".to_string()],
+                    ..Default::default()
+                },
+                "We received your message".to_string(),
+                Some(MessageBody::from_text("Thanks!".to_string())),
+                None,
+                None,
+            )
+        );
+    }
+
+    #[test]
+    fn test_build_ack_email_with_template() {
+        let json = serde_json::json!({"email": "visitor@example.com", "data": {"name": "John"}});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_template_id": "d-ack"}"#,
+        );
+        let template_data = Some(serde_json::json!({"name": "John"}));
+        let email = build_ack_email(&json, &settings, &template_data)
+            .unwrap()
+            .unwrap();
+        assert_eq!(email.template_id.as_deref(), Some("d-ack"));
+        assert_eq!(email.template_data, template_data);
+    }
+
+    #[test]
+    fn test_build_ack_email_requires_single_submitter() {
+        let json = serde_json::json!({"email": ["a@example.com", "b@example.com"]});
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_message": "Thanks!"}"#,
+        );
+        let result = build_ack_email(&json, &settings, &None);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "'email' field must be a string"
+        );
+    }
+
+    #[test]
+    fn test_send_outcome_report() {
+        let result = SendResult {
+            status: 202,
+            message_id: Some("abc123".to_string()),
+            errors: vec![],
+            retry_after: None,
+            timed_out: false,
+            internal: false,
+        };
+        let notification = SendOutcome::new("sendgrid", result, vec![202]);
+        assert!(notification.is_success());
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({"status": "queued", "provider": "sendgrid", "message_id": "abc123", "attempts": 1})
+        );
+
+        let result = SendResult::transport_error(&anyhow::anyhow!("connection refused"));
+        let notification = SendOutcome::new("sendgrid", result, vec![503, 0]);
+        assert!(!notification.is_success());
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({"status": "failed", "provider": "sendgrid", "attempts": 2})
+        );
+    }
+
+    #[test]
+    fn test_extract_recipients_invalid_address() {
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let invalid = [
+            (serde_json::json!({"email": ""}), "Invalid email address '' in 'email' field: empty address"),
+            (serde_json::json!({"email": "not-an-email"}), "Invalid email address 'not-an-email' in 'email' field: missing '@'"),
+            (serde_json::json!({"email": "to@example.com", "bcc": ["ok@example.com", "x@localhost"]}), "Invalid email address 'x@localhost' in 'bcc' field: domain 'localhost' must contain a dot"),
+            (serde_json::json!({"email": null}), "'email' field must be a string or an array of strings"),
+            (serde_json::json!({"email": 42}), "'email' field must be a string or an array of strings"),
+        ];
+        for (json, error) in invalid {
+            let result = extract_recipients(&json, &settings);
+            assert_eq!(result.unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "subject": "From {{name}}"}"#,
+        );
+        let code = |result: anyhow::Result<String>| {
+            let error = ApiError::from(result.unwrap_err());
+            (error.code, error.field)
+        };
+
+        let json = serde_json::json!({"email": "to@example.com", "cc": "x@localhost"});
+        let error = ApiError::from(extract_recipients(&json, &settings).unwrap_err());
+        assert_eq!(error.code, ErrorCode::InvalidEmail);
+        assert_eq!(error.field.as_deref(), Some("cc"));
+
+        // the setting is named in the message, without losing the code
+        let result = extract_subject(&serde_json::json!({}), &settings);
+        assert_eq!(
+            result.as_ref().unwrap_err().to_string(),
+            "Missing 'name' field in request body, used in 'subject' setting"
+        );
+        assert_eq!(
+            code(result),
+            (ErrorCode::MissingField, Some("name".to_string()))
+        );
+        assert_eq!(
+            code(extract_submitter(&serde_json::json!({"email": 42}))),
+            (ErrorCode::InvalidField, Some("email".to_string()))
+        );
+
+        let error =
+            ApiError::from(parse_request_body("application/json", b"{", &settings).unwrap_err());
+        assert_eq!(error.code, ErrorCode::InvalidBody);
+    }
+
+    /// Replays the given results, recording the emails it was asked to send.
+    struct FakeProvider {
+        name: &'static str,
+        results: std::cell::RefCell<Vec<SendResult>>,
+        sent: std::cell::RefCell<Vec<Email>>,
+    }
+
+    impl MailProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn send(&self, email: &Email, _timeouts: transport::Timeouts) -> SendResult {
+            self.sent.borrow_mut().push(email.clone());
+            self.results.borrow_mut().remove(0)
+        }
+    }
+
+    #[test]
+    fn test_send_with_provider() {
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let email = build_ack_email(
+            &serde_json::json!({"email": "visitor@example.com"}),
+            &settings_with(
+                r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_message": "Thanks!"}"#,
+            ),
+            &None,
+        )
+        .unwrap()
+        .unwrap();
+        let result = |status: u16| SendResult {
+            status,
+            message_id: Some("abc123".to_string()),
+            errors: vec![],
+            retry_after: None,
+            timed_out: false,
+            internal: false,
+        };
+
+        let provider = FakeProvider {
+            name: "fake",
+            results: vec![result(202)].into(),
+            sent: vec![].into(),
+        };
+        let outcome = send(
+            &[&provider],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert!(outcome.is_success());
+        assert_eq!(outcome.message_id.as_deref(), Some("abc123"));
+        assert_eq!(
+            provider.sent.borrow().as_slice(),
+            std::slice::from_ref(&email)
+        );
+
+        // client errors are not retried
+        let provider = FakeProvider {
+            name: "fake",
+            results: vec![result(400), result(202)].into(),
+            sent: vec![].into(),
+        };
+        let outcome = send(
+            &[&provider],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert!(!outcome.is_success());
+        assert_eq!(outcome.message_id, None);
+        assert_eq!(outcome.attempts, vec![400]);
+    }
+
+    #[test]
+    fn test_send_failover() {
+        let settings = settings_with(
+            r#"{"api_key": "test_value", "email_from": "from@example.com", "retry_max_attempts": 1}"#,
+        );
+        let email = build_ack_email(
+            &serde_json::json!({"email": "visitor@example.com"}),
+            &settings_with(
+                r#"{"api_key": "test_value", "email_from": "from@example.com", "ack_message": "Thanks!"}"#,
+            ),
+            &None,
+        )
+        .unwrap()
+        .unwrap();
+        let result = |status: u16| SendResult {
+            status,
+            message_id: Some("abc123".to_string()),
+            errors: vec![],
+            retry_after: None,
+            timed_out: false,
+            internal: false,
+        };
+        let fake = |name: &'static str, results: Vec<SendResult>| FakeProvider {
+            name,
+            results: results.into(),
+            sent: vec![].into(),
+        };
+
+        // unavailable, the secondary provider delivers the email
+        let primary = fake("primary", vec![result(503)]);
+        let secondary = fake("secondary", vec![result(200)]);
+        let outcome = send(
+            &[&primary, &secondary],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert!(outcome.is_success());
+        assert_eq!(outcome.provider, "secondary");
+        assert_eq!(outcome.attempts, vec![503, 200]);
+        assert_eq!(
+            secondary.sent.borrow().as_slice(),
+            std::slice::from_ref(&email)
+        );
+
+        let primary = fake(
+            "primary",
+            vec![SendResult::transport_error(&anyhow::anyhow!(
+                "connection refused"
+            ))],
+        );
+        let secondary = fake("secondary", vec![result(502)]);
+        let outcome = send(
+            &[&primary, &secondary],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert!(!outcome.is_success());
+        assert_eq!(
+            (outcome.provider, outcome.upstream_status),
+            ("secondary", 502)
+        );
+        assert_eq!(outcome.attempts, vec![0, 502]);
+
+        // the request itself was rejected, another provider wouldn't do better
+        let primary = fake("primary", vec![result(400)]);
+        let secondary = fake("secondary", vec![result(200)]);
+        let outcome = send(
+            &[&primary, &secondary],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert!(!outcome.is_success());
+        assert_eq!(outcome.provider, "primary");
+        assert!(secondary.sent.borrow().is_empty());
+
+        // a payload that can't be built isn't retried, nor sent elsewhere
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let internal = SendResult::internal_error(&anyhow::anyhow!("invalid payload"));
+        let primary = fake("primary", vec![internal]);
+        let secondary = fake("secondary", vec![result(200)]);
+        let outcome = send(
+            &[&primary, &secondary],
+            &FakeTransport::default(),
+            &email,
+            &settings,
+            "notification",
+            "req",
+        );
+        assert_eq!(outcome.attempts, vec![0]);
+        assert!(secondary.sent.borrow().is_empty());
+        assert_eq!(outcome.to_error(false).code, ErrorCode::InternalError);
+    }
+
+    #[test]
+    fn test_send_outcome_error() {
+        let result = |status: u16| SendResult {
+            status,
+            message_id: None,
+            errors: vec![serde_json::json!({"message": "Bad API key"})],
+            retry_after: None,
+            timed_out: false,
+            internal: false,
+        };
+        let error = SendOutcome::new("sendgrid", result(401), vec![401]).to_error(false);
+        assert_eq!((error.code, error.status), (ErrorCode::UpstreamError, 502));
+        assert_eq!(error.message, "The email couldn't be sent");
+        assert_eq!(error.details, None);
+
+        let error = SendOutcome::new("sendgrid", result(401), vec![401]).to_error(true);
+        assert_eq!(
+            error.details,
+            Some(Box::new(serde_json::json!({
+                "status": 401,
+                "errors": [{"message": "Bad API key"}],
+                "attempts": [401],
+            })))
+        );
+
+        let error = SendOutcome::new("sendgrid", result(429), vec![429]).to_error(false);
+        assert_eq!((error.code, error.status), (ErrorCode::RateLimited, 429));
+    }
+
+    #[test]
+    fn test_incoming_request_id() {
+        let headers =
+            |id: &str| HashMap::from([(REQUEST_ID_HEADER.to_string(), vec![id.to_string()])]);
+        assert_eq!(
+            incoming_request_id(&headers(" req-42.a_b ")),
+            Some("req-42.a_b".to_string())
+        );
+        assert_eq!(incoming_request_id(&headers("")), None);
+        assert_eq!(incoming_request_id(&headers("a\"b")), None);
+        assert_eq!(incoming_request_id(&headers(&"a".repeat(129))), None);
+        assert_eq!(incoming_request_id(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_extract_recipients_normalizes_addresses() {
+        let json = serde_json::json!({"email": "John <John@Bücher.Example>"});
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let result = extract_recipients(&json, &settings).unwrap();
+        assert_eq!(result.to, vec!["John@xn--bcher-kva.example"]);
+    }
+
+    #[test]
+    fn test_extract_recipients_invalid_type() {
+        let json = serde_json::json!({"email": "to@example.com", "cc": [42]});
+        let settings =
+            settings_with(r#"{"api_key": "test_value", "email_from": "from@example.com"}"#);
+        let result = extract_recipients(&json, &settings);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "'cc' field must be a string or an array of strings"
+        );
+    }
+
+    /// Replays the given responses, recording requests and delays.
+    /// Its clock only moves when sleeping, and by `latency` for each request. Requests slower than
+    /// their timeout, or answered with a [`Timeout`] error, time out after using all of it.
+    #[derive(Default)]
+    struct FakeTransport {
+        responses: std::cell::RefCell<Vec<anyhow::Result<HttpResponse>>>,
+        requests: std::cell::RefCell<Vec<HttpRequest>>,
+        sleeps: std::cell::RefCell<Vec<Duration>>,
+        latency: Duration,
+        clock: std::cell::Cell<Duration>,
+    }
+
+    impl FakeTransport {
+        fn new(responses: Vec<anyhow::Result<HttpResponse>>) -> Self {
+            Self {
+                responses: responses.into(),
+                ..Default::default()
+            }
+        }
+
+        fn urls(&self) -> Vec<String> {
+            let requests = self.requests.borrow();
+            requests.iter().map(|request| request.url.clone()).collect()
+        }
+    }
+
+    impl Transport for FakeTransport {
+        fn post(&self, request: &HttpRequest) -> anyhow::Result<HttpResponse> {
+            self.requests.borrow_mut().push(request.clone());
+            let response = self.responses.borrow_mut().remove(0);
+            let timed_out = match &response {
+                Err(e) => e.is::<Timeout>(),
+                Ok(_) => self.latency >= request.timeouts.total,
+            };
+            if timed_out {
+                self.clock.set(self.clock.get() + request.timeouts.total);
+                return Err(Timeout.into());
+            }
+            self.clock.set(self.clock.get() + self.latency);
+            response
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.borrow_mut().push(duration);
+            self.clock.set(self.clock.get() + duration);
+        }
+
+        fn now(&self) -> Duration {
+            self.clock.get()
+        }
+    }
+
+    // responses recorded from SendGrid's v3 Mail Send API
+    fn sendgrid_accepted() -> anyhow::Result<HttpResponse> {
+        Ok(HttpResponse {
+            status: 202,
+            headers: [("x-message-id".to_string(), "W8EyCyVqQ9m1bX2z".to_string())].into(),
+            body: vec![],
+        })
+    }
+
+    fn sendgrid_error(status: u16, message: &str) -> anyhow::Result<HttpResponse> {
+        let body =
+            serde_json::json!({"errors": [{"message": message, "field": null, "help": null}]});
+        Ok(HttpResponse {
+            status,
+            headers: [("retry-after".to_string(), "1".to_string())]
+                .into_iter()
+                .filter(|_| status == 429)
+                .collect(),
+            body: body.to_string().into_bytes(),
+        })
+    }
+
+    fn post_request(settings: &str, body: &str) -> Request {
+        Request {
+            method: Method::Post,
+            headers: HashMap::from([
+                (
+                    "x-edgee-component-settings".to_string(),
+                    vec![settings.to_string()],
+                ),
+                (
+                    "content-type".to_string(),
+                    vec!["application/json".to_string()],
+                ),
+            ]),
+            body: Ok(body.as_bytes().to_vec()),
+        }
+    }
+
+    const SETTINGS: &str =
+        r#"{"api_key": "SG.key", "email_from": "from@example.com", "retry_base_delay": 100}"#;
+    const BODY: &str = r#"{"email": "to@example.com", "message": "Hello"}"#;
+
+    fn response_json(response: &helpers::ResponseBuilder) -> serde_json::Value {
+        serde_json::from_str(response.body().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_handle_request_sends_email() {
+        let transport = FakeTransport::new(vec![sendgrid_accepted()]);
+        let response = handle_request(&post_request(SETTINGS, BODY), &transport, "req-1");
+
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(response.header("x-request-id"), Some("req-1"));
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({"status": "queued", "provider": "sendgrid", "message_id": "W8EyCyVqQ9m1bX2z", "attempts": 1})
+        );
+
+        let requests = transport.requests.borrow();
+        assert_eq!(requests[0].url, "https://api.sendgrid.com/v3/mail/send");
+        assert!(requests[0]
+            .headers
+            .contains(&("Authorization".to_string(), "Bearer SG.key".to_string())));
+        let payload: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            payload["personalizations"][0]["to"],
+            serde_json::json!([{"email": "to@example.com"}])
+        );
+        assert_eq!(
+            payload["from"],
+            serde_json::json!({"email": "from@example.com"})
+        );
+    }
+
+    #[test]
+    fn test_handle_request_sender_name() {
+        let transport = FakeTransport::new(vec![sendgrid_accepted()]);
+        let settings = r#"{"api_key": "SG.key", "email_from": "Acme <from@example.com>"}"#;
+        handle_request(&post_request(settings, BODY), &transport, "req-1");
+        let payload: serde_json::Value =
+            serde_json::from_slice(&transport.requests.borrow()[0].body).unwrap();
+        assert_eq!(
+            payload["from"],
+            serde_json::json!({"email": "from@example.com", "name": "Acme"})
+        );
+
+        // the visitor's name is only used for the owner, never in the acknowledgement they receive
+        let transport = FakeTransport::new(vec![sendgrid_accepted(), sendgrid_accepted()]);
+        let settings = r#"{"api_key": "SG.key", "email_from": "Acme <from@example.com>", "from_name": "{{name}} via Website", "ack_message": "Thanks!"}"#;
+        let body = r#"{"email": "to@example.com", "name": "Your Bank", "message": "Hello"}"#;
+        handle_request(&post_request(settings, body), &transport, "req-1");
+        let requests = transport.requests.borrow();
+        let from = |index: usize| {
+            let payload: serde_json::Value = serde_json::from_slice(&requests[index].body).unwrap();
+            payload["from"].clone()
+        };
+        assert_eq!(
+            from(0),
+            serde_json::json!({"email": "from@example.com", "name": "Your Bank via Website"})
+        );
+        assert_eq!(
+            from(1),
+            serde_json::json!({"email": "from@example.com", "name": "Acme"})
+        );
+    }
+
+    #[test]
+    fn test_handle_request_api_base_url() {
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "api_base_url": "http://localhost:8080", "test_mode": true}"#;
+        let transport = FakeTransport::new(vec![sendgrid_accepted()]);
+        handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(transport.urls(), vec!["http://localhost:8080/v3/mail/send"]);
+    }
+
+    #[test]
+    fn test_handle_request_routes() {
+        let transport = FakeTransport::default();
+        let request = |method: Method, settings: &str| Request {
+            method,
+            headers: HashMap::from([(
+                "x-edgee-component-settings".to_string(),
+                vec![settings.to_string()],
+            )]),
+            body: Ok(vec![]),
+        };
+
+        let response = handle_request(&request(Method::Options, SETTINGS), &transport, "req-1");
+        assert_eq!(response.status_code(), 204);
+
+        let response = handle_request(&request(Method::Get, SETTINGS), &transport, "req-1");
+        assert_eq!(response.status_code(), 405);
+        assert_eq!(response.header("allow"), Some("POST, OPTIONS"));
+        assert_eq!(
+            response_json(&response)["error"]["code"],
+            "method_not_allowed"
+        );
+
+        let settings =
+            r#"{"api_key": "SG.key", "email_from": "from@example.com", "health_check": true}"#;
+        let response = handle_request(&request(Method::Get, settings), &transport, "req-1");
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response_json(&response)["status"], "ok");
+
+        // preflights allow the health check too
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "health_check": true, "allowed_origins": "*"}"#;
+        let mut preflight = request(Method::Options, settings);
+        preflight.headers.insert(
+            "origin".to_string(),
+            vec!["https://www.example.com".to_string()],
+        );
+        let response = handle_request(&preflight, &transport, "req-1");
+        assert_eq!(
+            response.header("access-control-allow-methods"),
+            Some("GET, POST, OPTIONS")
+        );
+
+        let response = handle_request(&request(Method::Post, "{}"), &transport, "req-1");
+        assert_eq!(response.status_code(), 500);
+        assert_eq!(
+            response_json(&response)["error"]["code"],
+            "invalid_settings"
+        );
+        assert_eq!(response.header("x-request-id"), Some("req-1"));
+
+        assert!(transport.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_handle_request_invalid_settings_cors() {
+        let transport = FakeTransport::default();
+        let settings =
+            r#"{"email_from": "from@example.com", "allowed_origins": "https://www.example.com"}"#;
+        let request = |method: Method| Request {
+            method,
+            headers: HashMap::from([
+                (
+                    "x-edgee-component-settings".to_string(),
+                    vec![settings.to_string()],
+                ),
+                (
+                    "origin".to_string(),
+                    vec!["https://www.example.com".to_string()],
+                ),
+            ]),
+            body: Ok(BODY.as_bytes().to_vec()),
+        };
+
+        // preflight succeeds, so the browser can read the actual error
+        let response = handle_request(&request(Method::Options), &transport, "req-1");
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("https://www.example.com")
+        );
+        assert_eq!(
+            response.header("access-control-allow-methods"),
+            Some("POST, OPTIONS")
+        );
+
+        let response = handle_request(&request(Method::Post), &transport, "req-1");
+        assert_eq!(response.status_code(), 500);
+        assert_eq!(
+            response_json(&response)["error"]["code"],
+            "invalid_settings"
+        );
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("https://www.example.com")
+        );
+        assert!(transport.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_handle_request_invalid_settings_hides_values() {
+        let transport = FakeTransport::default();
+        let invalid = [
+            (
+                r#"{"email_from": "from@example.com"}"#,
+                "Invalid component settings, check the 'api_key' setting",
+            ),
+            (
+                r#"{"api_key": "SG.key", "email_from": "SG.secret-key"}"#,
+                "Invalid component settings, check the 'email_from' setting",
+            ),
+            (
+                r#"{"api_key": "SG.key", "email_from": "from@example.com", "subject": ["SG.secret-key"]}"#,
+                "Invalid component settings, check the 'subject' setting",
+            ),
+            ("SG.secret-key", "Invalid component settings"),
+        ];
+        for (settings, message) in invalid {
+            let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+            assert_eq!(response.status_code(), 500);
+            assert_eq!(
+                response_json(&response)["error"]["message"],
+                message,
+                "{settings}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_handle_request_invalid_body() {
+        let transport = FakeTransport::default();
+        let response = handle_request(&post_request(SETTINGS, "{"), &transport, "req-1");
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(response_json(&response)["error"]["code"], "invalid_body");
+
+        let mut request = post_request(SETTINGS, BODY);
+        request.body = Err(ApiError::new(
+            ErrorCode::InvalidBody,
+            "Failed to read from request stream",
+        ));
+        let response = handle_request(&request, &transport, "req-1");
+        assert_eq!(
+            response_json(&response)["error"],
+            serde_json::json!({
+                "code": "invalid_body",
+                "message": "Failed to read from request stream",
+                "request_id": "req-1",
+            })
+        );
+
+        let response = handle_request(
+            &post_request(SETTINGS, r#"{"email": "to@", "message": "Hello"}"#),
+            &transport,
+            "req-1",
+        );
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(response_json(&response)["error"]["field"], "email");
+
+        assert!(transport.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_handle_request_upstream_errors() {
+        // rejected emails aren't retried, and SendGrid's errors aren't passed on
+        let transport = FakeTransport::new(vec![sendgrid_error(
+            400,
+            "The from address does not match a verified Sender Identity.",
+        )]);
+        let response = handle_request(&post_request(SETTINGS, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 502);
+        assert_eq!(
+            response_json(&response)["error"],
+            serde_json::json!({
+                "code": "upstream_error",
+                "message": "The email couldn't be sent",
+                "request_id": "req-1",
+            })
+        );
+
+        // rate limits are retried after Retry-After
+        let transport = FakeTransport::new(vec![
+            sendgrid_error(429, "too many requests"),
+            sendgrid_error(429, "too many requests"),
+            sendgrid_error(429, "too many requests"),
+        ]);
+        let response = handle_request(&post_request(SETTINGS, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(response_json(&response)["error"]["code"], "rate_limited");
+        assert_eq!(
+            *transport.sleeps.borrow(),
+            vec![std::time::Duration::from_secs(1); 2]
+        );
+
+        // transient failures are retried with backoff
+        let transport = FakeTransport::new(vec![
+            sendgrid_error(503, "Service Unavailable"),
+            Err(anyhow::anyhow!("connection reset")),
+            sendgrid_accepted(),
+        ]);
+        let response = handle_request(&post_request(SETTINGS, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(response_json(&response)["attempts"], 3);
+        assert_eq!(
+            *transport.sleeps.borrow(),
+            vec![
+                std::time::Duration::from_millis(100),
+                std::time::Duration::from_millis(200)
+            ]
+        );
+
+        // details are only shown in debug mode
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "debug": true}"#;
+        let transport = FakeTransport::new(vec![sendgrid_error(401, "Permission denied")]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(
+            response_json(&response)["error"]["details"],
+            serde_json::json!({
+                "status": 401,
+                "errors": [{"message": "Permission denied", "field": null, "help": null}],
+                "attempts": [401],
+            })
+        );
+    }
+
+    #[test]
+    fn test_handle_request_timeouts() {
+        let timeouts = |connect: u64, total: u64| transport::Timeouts {
+            connect: Duration::from_millis(connect),
+            total: Duration::from_millis(total),
+        };
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "retry_base_delay": 100, "connect_timeout": 1000, "request_timeout": 2000, "timeout": 5000}"#;
+
+        // timed out attempts are retried, each within the time left
+        let transport = FakeTransport::new(vec![Err(Timeout.into()), sendgrid_accepted()]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(response_json(&response)["attempts"], 2);
+
+        let transport = FakeTransport::new(vec![
+            Err(Timeout.into()),
+            Err(Timeout.into()),
+            Err(Timeout.into()),
+        ]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 504);
+        assert_eq!(
+            response_json(&response)["error"],
+            serde_json::json!({
+                "code": "timeout",
+                "message": "The email service didn't respond in time",
+                "request_id": "req-1",
+            })
+        );
+        let requests = transport.requests.borrow();
+        let sent: Vec<_> = requests.iter().map(|request| request.timeouts).collect();
+        assert_eq!(
+            sent,
+            vec![
+                timeouts(1000, 2000),
+                timeouts(1000, 2000),
+                timeouts(700, 700)
+            ]
+        );
+        assert_eq!(transport.now(), Duration::from_secs(5));
+
+        // no retry that couldn't start before the deadline
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "retry_base_delay": 2000, "timeout": 3000}"#;
+        let transport = FakeTransport {
+            latency: Duration::from_secs(2),
+            ..FakeTransport::new(vec![sendgrid_error(503, "Service Unavailable")])
+        };
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 502);
+        assert!(transport.sleeps.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_handle_request_failover_after_timeouts() {
+        // SendGrid hangs: its attempts leave time for the failover provider
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "retry_base_delay": 100, "request_timeout": 2000, "timeout": 5000, "failover_url": "https://relay.example.com/send"}"#;
+        let transport = FakeTransport::new(vec![
+            Err(Timeout.into()),
+            Err(Timeout.into()),
+            Ok(HttpResponse {
+                status: 200,
+                headers: HashMap::new(),
+                body: br#"{"id": "relay-1"}"#.to_vec(),
+            }),
+        ]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({"status": "queued", "provider": "http", "message_id": "relay-1", "attempts": 3})
+        );
+        assert_eq!(
+            transport.urls(),
+            vec![
+                "https://api.sendgrid.com/v3/mail/send",
+                "https://api.sendgrid.com/v3/mail/send",
+                "https://relay.example.com/send"
+            ]
+        );
+        let requests = transport.requests.borrow();
+        assert_eq!(requests[1].timeouts.total, Duration::from_millis(900));
+        assert_eq!(requests[2].timeouts.total, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_handle_request_failover() {
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "retry_max_attempts": 1, "failover_url": "https://relay.example.com/send", "failover_api_key": "relay-key"}"#;
+        let transport = FakeTransport::new(vec![
+            Err(anyhow::anyhow!("connection refused")),
+            Ok(HttpResponse {
+                status: 200,
+                headers: HashMap::new(),
+                body: br#"{"id": "relay-1"}"#.to_vec(),
+            }),
+        ]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({"status": "queued", "provider": "http", "message_id": "relay-1", "attempts": 2})
+        );
+        assert_eq!(
+            transport.urls(),
+            vec![
+                "https://api.sendgrid.com/v3/mail/send",
+                "https://relay.example.com/send"
+            ]
+        );
+        assert!(transport.requests.borrow()[1]
+            .headers
+            .contains(&("Authorization".to_string(), "Bearer relay-key".to_string())));
+    }
+
+    #[test]
+    fn test_handle_request_acknowledgement_skipped() {
+        // several submitters can't be acknowledged, the owner is still notified
+        let settings =
+            r#"{"api_key": "SG.key", "email_from": "from@example.com", "ack_message": "Thanks!"}"#;
+        let body = r#"{"email": ["a@example.com", "b@example.com"], "message": "Hello"}"#;
+        let transport = FakeTransport::new(vec![sendgrid_accepted()]);
+        let response = handle_request(&post_request(settings, body), &transport, "req-1");
+        assert_eq!(response.status_code(), 202);
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({
+                "notification": {"status": "queued", "provider": "sendgrid", "message_id": "W8EyCyVqQ9m1bX2z", "attempts": 1},
+                "acknowledgement": {"status": "skipped"},
+            })
+        );
+        assert_eq!(transport.requests.borrow().len(), 1);
+
+        // a subject placeholder missing from the body
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "ack_message": "Thanks!", "ack_subject": "Thanks {{name}}", "success_redirect": "/thanks"}"#;
+        let transport = FakeTransport::new(vec![sendgrid_accepted()]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 303);
+        assert_eq!(response.header("location"), Some("/thanks?ack=skipped"));
+        assert_eq!(transport.requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_handle_request_acknowledgement_and_redirects() {
+        let settings = r#"{"api_key": "SG.key", "email_from": "from@example.com", "ack_message": "Thanks!", "success_redirect": "/thanks", "error_redirect": "/contact?sent=0"}"#;
+        let transport = FakeTransport::new(vec![sendgrid_accepted(), sendgrid_accepted()]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 303);
+        assert_eq!(response.header("location"), Some("/thanks"));
+        assert_eq!(transport.requests.borrow().len(), 2);
+
+        // the notification was sent, but the visitor didn't get their copy
+        let transport = FakeTransport::new(vec![
+            sendgrid_accepted(),
+            sendgrid_error(400, "Bad Request"),
+        ]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 303);
+        assert_eq!(response.header("location"), Some("/thanks?ack=failed"));
+
+        let transport = FakeTransport::new(vec![sendgrid_error(400, "Bad Request")]);
+        let response = handle_request(&post_request(settings, BODY), &transport, "req-1");
+        assert_eq!(response.status_code(), 303);
+        assert_eq!(
+            response.header("location"),
+            Some("/contact?sent=0&error=upstream_error")
+        );
+        // no acknowledgement for emails that weren't delivered
+        assert_eq!(transport.requests.borrow().len(), 1);
     }
 }
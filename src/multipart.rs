@@ -0,0 +1,165 @@
+/// A single part of a multipart/form-data body.
+#[derive(Debug, PartialEq)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>, // only set for file inputs
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+}
+
+/// Extracts the boundary from a `multipart/form-data; boundary=...` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> anyhow::Result<Vec<Part>> {
+    let delimiter = format!("--{boundary}").into_bytes();
+    let malformed = || anyhow::anyhow!("Malformed multipart/form-data body");
+
+    let mut parts = Vec::new();
+    let mut pos = find(body, &delimiter, 0).ok_or_else(malformed)? + delimiter.len();
+    loop {
+        // the last delimiter is followed by "--"
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err(malformed());
+        }
+        pos += 2;
+
+        let headers_end = find(body, b"\r\n\r\n", pos).ok_or_else(malformed)?;
+        let headers = std::str::from_utf8(&body[pos..headers_end]).map_err(|_| malformed())?;
+        let data_start = headers_end + 4;
+
+        let mut closing = b"\r\n".to_vec();
+        closing.extend_from_slice(&delimiter);
+        let data_end = find(body, &closing, data_start).ok_or_else(malformed)?;
+
+        parts.push(parse_part(headers, body[data_start..data_end].to_vec())?);
+        pos = data_end + closing.len();
+    }
+}
+
+fn parse_part(headers: &str, data: Vec<u8>) -> anyhow::Result<Part> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in headers.split("\r\n") {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        if key == "content-disposition" {
+            for param in value.split(';').skip(1) {
+                let Some((param_key, param_value)) = param.split_once('=') else {
+                    continue;
+                };
+                let param_value = param_value.trim().trim_matches('"').to_string();
+                match param_key.trim().to_ascii_lowercase().as_str() {
+                    "name" => name = Some(param_value),
+                    "filename" => filename = Some(param_value),
+                    _ => {}
+                }
+            }
+        } else if key == "content-type" {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    let name =
+        name.ok_or_else(|| anyhow::anyhow!("Missing field name in multipart/form-data body"))?;
+    Ok(Part {
+        name,
+        filename,
+        content_type,
+        data,
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| index + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"email\"\r\n\
+        \r\n\
+        test@example.com\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"cv\"; filename=\"cv.pdf\"\r\n\
+        Content-Type: application/pdf\r\n\
+        \r\n\
+        %PDF\r\n--not-a-boundary\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=XyZ"),
+            Some("XyZ".to_string())
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\""),
+            Some("a b".to_string())
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("application/json; boundary=XyZ"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let parts = parse(BODY.as_bytes(), "XyZ").unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                Part {
+                    name: "email".to_string(),
+                    filename: None,
+                    content_type: None,
+                    data: b"test@example.com".to_vec(),
+                },
+                Part {
+                    name: "cv".to_string(),
+                    filename: Some("cv.pdf".to_string()),
+                    content_type: Some("application/pdf".to_string()),
+                    data: b"%PDF\r\n--not-a-boundary".to_vec(),
+                },
+            ]
+        );
+        assert!(!parts[0].is_file());
+        assert!(parts[1].is_file());
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(parse(b"no boundary here", "XyZ").is_err());
+        assert!(parse(b"--XyZ\r\nContent-Disposition: form-data", "XyZ").is_err());
+        assert!(parse(b"--XyZ\r\n\r\nvalue\r\n--XyZ--", "XyZ").is_err());
+    }
+}
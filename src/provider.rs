@@ -0,0 +1,143 @@
+use serde_json::Value;
+
+use crate::email::Email;
+use crate::transport::{HttpResponse, Timeout, Timeouts};
+
+/// An email service, such as SendGrid. The handler only talks to this trait.
+pub trait MailProvider {
+    /// Short name, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Makes a single attempt to send the email, retries are up to the caller.
+    fn send(&self, email: &Email, timeouts: Timeouts) -> SendResult;
+}
+
+/// Normalized result of a single call to a provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendResult {
+    /// the provider's status code, or 0 if the request couldn't be sent
+    pub status: u16,
+    pub message_id: Option<String>,
+    /// the provider's own errors, only meant for logs
+    pub errors: Vec<serde_json::Value>,
+    /// the provider's `Retry-After` header, if any
+    pub retry_after: Option<String>,
+    /// the provider didn't answer in time (status is 0)
+    pub timed_out: bool,
+    /// the request couldn't be built, retrying or failing over won't help (status is 0)
+    pub internal: bool,
+}
+
+impl SendResult {
+    /// Normalizes a provider's response. The message ID of accepted emails comes from the
+    /// `X-Message-Id` header or a `message_id`/`id` field, and errors from an `errors` array.
+    pub fn from_response(response: &HttpResponse) -> Self {
+        let json: Option<Value> = serde_json::from_slice(&response.body).ok();
+        let field = |name: &str| {
+            json.as_ref()
+                .and_then(|json| json.get(name))
+                .and_then(Value::as_str)
+                .map(String::from)
+        };
+        let mut result = Self {
+            status: response.status,
+            message_id: response
+                .header("x-message-id")
+                .or_else(|| field("message_id"))
+                .or_else(|| field("id")),
+            errors: vec![],
+            retry_after: response.header("retry-after"),
+            timed_out: false,
+            internal: false,
+        };
+        if !result.is_success() {
+            result.message_id = None;
+            result.errors = match json.as_ref().and_then(|json| json.get("errors")) {
+                Some(Value::Array(errors)) => errors.clone(),
+                _ if response.body.is_empty() => vec![],
+                _ => vec![String::from_utf8_lossy(&response.body).into()],
+            };
+        }
+        result
+    }
+
+    /// The request couldn't be built, such as a payload that can't be serialized.
+    pub fn internal_error(error: &anyhow::Error) -> Self {
+        Self {
+            internal: true,
+            ..Self::transport_error(error)
+        }
+    }
+
+    /// The request couldn't be sent at all.
+    pub fn transport_error(error: &anyhow::Error) -> Self {
+        Self {
+            status: 0,
+            message_id: None,
+            errors: vec![error.to_string().into()],
+            retry_after: None,
+            timed_out: error.is::<Timeout>(),
+            internal: false,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The provider is down or unreachable, another one may deliver the email.
+    pub fn is_unavailable(&self) -> bool {
+        !self.internal && (self.status == 0 || self.status >= 500)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_from_response() {
+        let result = SendResult::from_response(&response(202, &[("x-message-id", "abc123")], b""));
+        assert!(result.is_success());
+        assert_eq!(result.message_id.as_deref(), Some("abc123"));
+        assert!(result.errors.is_empty());
+
+        let result = SendResult::from_response(&response(200, &[], br#"{"id": "msg-1"}"#));
+        assert_eq!(result.message_id.as_deref(), Some("msg-1"));
+
+        let body = br#"{"errors": [{"message": "Too many requests"}]}"#;
+        let headers = [("x-message-id", "ignored"), ("retry-after", "2")];
+        let result = SendResult::from_response(&response(429, &headers, body));
+        assert!(!result.is_success());
+        assert_eq!(result.message_id, None);
+        assert_eq!(result.retry_after.as_deref(), Some("2"));
+        assert_eq!(result.errors, vec![json!({"message": "Too many requests"})]);
+
+        let result = SendResult::from_response(&response(502, &[], b"Bad Gateway"));
+        assert_eq!(result.errors, vec![json!("Bad Gateway")]);
+        assert!(result.is_unavailable());
+        assert!(SendResult::from_response(&response(500, &[], b""))
+            .errors
+            .is_empty());
+    }
+
+    #[test]
+    fn test_internal_error() {
+        let result = SendResult::internal_error(&anyhow::anyhow!("invalid payload"));
+        assert_eq!(result.status, 0);
+        assert!(!result.is_unavailable());
+        assert!(SendResult::transport_error(&anyhow::anyhow!("connection reset")).is_unavailable());
+    }
+}
@@ -0,0 +1,155 @@
+//! Retries of transient SendGrid failures, with exponential backoff, within an overall deadline.
+
+use std::time::Duration;
+
+use crate::transport::Timeouts;
+
+/// Longest wait between two attempts, so visitors aren't kept waiting.
+/// A `Retry-After` asking for more gives up instead.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub connect_timeout: Duration,
+    /// for a single attempt, so that a hung call leaves time to retry or fail over
+    pub request_timeout: Duration,
+    /// deadline for sending an email, all attempts, delays and providers included
+    pub timeout: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before the next attempt, or `None` if the failure shouldn't be retried.
+    /// `attempts` is the number of attempts made so far, and `status` 0 a transport failure.
+    pub fn delay(&self, attempts: u32, status: u16, retry_after: Option<&str>) -> Option<Duration> {
+        if attempts >= self.max_attempts || !is_retryable(status) {
+            return None;
+        }
+        let delay = match retry_after.and_then(parse_retry_after) {
+            Some(delay) => delay,
+            None => self
+                .base_delay
+                .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1))),
+        };
+        match retry_after {
+            Some(_) if delay > MAX_DELAY => None,
+            _ => Some(delay.min(MAX_DELAY)),
+        }
+    }
+
+    /// Timeouts of an attempt with `left` before the deadline, or `None` if it has passed.
+    pub fn timeouts(&self, left: Duration) -> Option<Timeouts> {
+        let total = self.request_timeout.min(left);
+        if total.is_zero() {
+            return None;
+        }
+        Some(Timeouts {
+            connect: self.connect_timeout.min(total),
+            total,
+        })
+    }
+
+    /// Time for a provider's attempts, from the start of the send, when `remaining` providers
+    /// can still be failed over to: each of them is left an attempt, but the first one always gets one.
+    pub fn budget(&self, remaining: u32) -> Duration {
+        let reserved = self.request_timeout.saturating_mul(remaining);
+        self.timeout
+            .saturating_sub(reserved)
+            .max(self.request_timeout)
+            .min(self.timeout)
+    }
+}
+
+/// Rate limits, server errors and transport failures are transient, other errors won't change on retry.
+fn is_retryable(status: u16) -> bool {
+    status == 0 || status == 429 || status >= 500
+}
+
+/// `Retry-After` in seconds (SendGrid doesn't send HTTP dates).
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(500),
+        connect_timeout: Duration::from_secs(5),
+        request_timeout: Duration::from_secs(8),
+        timeout: Duration::from_secs(20),
+    };
+
+    #[test]
+    fn test_delay_backoff() {
+        assert_eq!(POLICY.delay(1, 503, None), Some(Duration::from_millis(500)));
+        assert_eq!(POLICY.delay(2, 0, None), Some(Duration::from_secs(1)));
+        assert_eq!(POLICY.delay(3, 429, None), Some(Duration::from_secs(2)));
+        assert_eq!(POLICY.delay(4, 503, None), None);
+
+        let slow = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(5),
+            ..POLICY
+        };
+        assert_eq!(slow.delay(5, 503, None), Some(MAX_DELAY));
+    }
+
+    #[test]
+    fn test_delay_not_retryable() {
+        assert_eq!(POLICY.delay(1, 202, None), None);
+        assert_eq!(POLICY.delay(1, 400, None), None);
+        assert_eq!(POLICY.delay(1, 401, None), None);
+    }
+
+    #[test]
+    fn test_delay_retry_after() {
+        assert_eq!(
+            POLICY.delay(1, 429, Some("3")),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(POLICY.delay(1, 429, Some(" 0 ")), Some(Duration::ZERO));
+        // too long to wait at the edge
+        assert_eq!(POLICY.delay(1, 429, Some("60")), None);
+        // not a number of seconds: backoff
+        assert_eq!(
+            POLICY.delay(1, 429, Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn test_timeouts() {
+        assert_eq!(
+            POLICY.timeouts(Duration::from_secs(20)),
+            Some(Timeouts {
+                connect: Duration::from_secs(5),
+                total: Duration::from_secs(8),
+            })
+        );
+        assert_eq!(
+            POLICY.timeouts(Duration::from_secs(3)),
+            Some(Timeouts {
+                connect: Duration::from_secs(3),
+                total: Duration::from_secs(3),
+            })
+        );
+        assert_eq!(POLICY.timeouts(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_budget() {
+        assert_eq!(POLICY.budget(0), Duration::from_secs(20));
+        assert_eq!(POLICY.budget(1), Duration::from_secs(12));
+        // at least one attempt, within the deadline
+        assert_eq!(POLICY.budget(2), Duration::from_secs(8));
+        let short = RetryPolicy {
+            timeout: Duration::from_secs(5),
+            ..POLICY
+        };
+        assert_eq!(short.budget(1), Duration::from_secs(5));
+    }
+}
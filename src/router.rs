@@ -0,0 +1,59 @@
+use crate::world::bindings::wasi::http::types::Method;
+
+/// What the component does with an incoming request.
+/// The component is mapped to a single path, so requests are dispatched on their method only.
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    /// POST: send the email(s)
+    SendEmail,
+    /// OPTIONS: answer the CORS preflight
+    Preflight,
+    /// GET, only if enabled in settings: health and configuration document
+    Health,
+    /// anything else: 405 with an `Allow` header
+    MethodNotAllowed,
+}
+
+pub fn route(method: &Method, health_check: bool) -> Route {
+    match method {
+        Method::Post => Route::SendEmail,
+        Method::Options => Route::Preflight,
+        Method::Get if health_check => Route::Health,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+/// Value of the `Allow` header for 405 responses.
+pub fn allowed_methods(health_check: bool) -> &'static str {
+    if health_check {
+        "GET, POST, OPTIONS"
+    } else {
+        "POST, OPTIONS"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route(&Method::Post, false), Route::SendEmail);
+        assert_eq!(route(&Method::Options, false), Route::Preflight);
+        assert_eq!(route(&Method::Get, false), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::Get, true), Route::Health);
+        assert_eq!(route(&Method::Put, true), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::Delete, false), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::Head, true), Route::MethodNotAllowed);
+        assert_eq!(
+            route(&Method::Other("PURGE".to_string()), true),
+            Route::MethodNotAllowed
+        );
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        assert_eq!(allowed_methods(false), "POST, OPTIONS");
+        assert_eq!(allowed_methods(true), "GET, POST, OPTIONS");
+    }
+}
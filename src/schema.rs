@@ -0,0 +1,359 @@
+//! Validation of request bodies against a JSON Schema, supporting a subset of draft 2020-12:
+//! `type`, `properties`, `required`, `items`, `minLength`, `maxLength`, `pattern`, `enum`
+//! and `format: "email"`. Other keywords are ignored, as are unknown formats.
+
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::email_address;
+
+const TYPES: &[&str] = &[
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+/// A compiled schema, keeping its source for serialization.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    source: Value,
+    root: Node,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    reject: bool, // `false` schema
+    types: Vec<String>,
+    properties: BTreeMap<String, Node>,
+    required: Vec<String>,
+    items: Option<Box<Node>>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    pattern: Option<Regex>,
+    enumeration: Option<Vec<Value>>,
+    email: bool,
+}
+
+/// A validation error, located with a JSON pointer such as `/data/name`.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl Schema {
+    pub fn compile(source: Value) -> anyhow::Result<Self> {
+        let root = compile_node(&source, "")?;
+        Ok(Self { source, root })
+    }
+
+    /// Returns all the errors, the body is valid if there is none.
+    pub fn validate(&self, body_json: &Value) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        validate_node(&self.root, body_json, "", &mut errors);
+        errors
+    }
+}
+
+impl serde::Serialize for Schema {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+fn compile_node(schema: &Value, path: &str) -> anyhow::Result<Node> {
+    let invalid = |message: &str| anyhow::anyhow!("{message} at '{path}'");
+    let schema = match schema {
+        Value::Bool(accept) => {
+            return Ok(Node {
+                reject: !accept,
+                ..Default::default()
+            })
+        }
+        Value::Object(schema) => schema,
+        _ => return Err(invalid("schema must be an object or a boolean")),
+    };
+
+    let mut node = Node::default();
+    for (keyword, value) in schema {
+        match keyword.as_str() {
+            "type" => {
+                node.types = match value {
+                    Value::String(name) => vec![name.to_string()],
+                    Value::Array(names) => names
+                        .iter()
+                        .map(|name| name.as_str().map(String::from))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| invalid("'type' must be a string or an array of strings"))?,
+                    _ => return Err(invalid("'type' must be a string or an array of strings")),
+                };
+                if let Some(name) = node
+                    .types
+                    .iter()
+                    .find(|name| !TYPES.contains(&name.as_str()))
+                {
+                    return Err(invalid(&format!("unknown type '{name}'")));
+                }
+            }
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| invalid("'properties' must be an object"))?;
+                for (name, property) in properties {
+                    let property_path = format!("{path}/properties/{}", escape_pointer(name));
+                    node.properties
+                        .insert(name.to_string(), compile_node(property, &property_path)?);
+                }
+            }
+            "required" => {
+                node.required = value
+                    .as_array()
+                    .and_then(|names| {
+                        names
+                            .iter()
+                            .map(|name| name.as_str().map(String::from))
+                            .collect()
+                    })
+                    .ok_or_else(|| invalid("'required' must be an array of strings"))?;
+            }
+            "items" => node.items = Some(Box::new(compile_node(value, &format!("{path}/items"))?)),
+            "minLength" | "maxLength" => {
+                let length = value
+                    .as_u64()
+                    .and_then(|length| usize::try_from(length).ok())
+                    .ok_or_else(|| {
+                        invalid(&format!("'{keyword}' must be a non-negative integer"))
+                    })?;
+                if keyword == "minLength" {
+                    node.min_length = Some(length);
+                } else {
+                    node.max_length = Some(length);
+                }
+            }
+            "pattern" => {
+                let pattern = value
+                    .as_str()
+                    .ok_or_else(|| invalid("'pattern' must be a string"))?;
+                node.pattern = Some(
+                    Regex::new(pattern)
+                        .map_err(|_| invalid(&format!("invalid 'pattern' '{pattern}'")))?,
+                );
+            }
+            "enum" => {
+                node.enumeration = Some(
+                    value
+                        .as_array()
+                        .cloned()
+                        .ok_or_else(|| invalid("'enum' must be an array"))?,
+                );
+            }
+            "format" => node.email = value.as_str() == Some("email"),
+            _ => {} // unsupported keywords are ignored, like unknown keywords
+        }
+    }
+    Ok(node)
+}
+
+fn validate_node(node: &Node, value: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+    let mut error = |message: String| {
+        errors.push(ValidationError {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    if node.reject {
+        return error("is not allowed".to_string());
+    }
+    if !node.types.is_empty() && !node.types.iter().any(|name| has_type(value, name)) {
+        return error(format!("must be of type {}", node.types.join(" or ")));
+    }
+    if let Some(values) = &node.enumeration {
+        if !values.contains(value) {
+            let values: Vec<String> = values.iter().map(Value::to_string).collect();
+            error(format!("must be one of {}", values.join(", ")));
+        }
+    }
+
+    match value {
+        Value::String(string) => {
+            let length = string.chars().count();
+            if let Some(min_length) = node.min_length.filter(|min| length < *min) {
+                error(format!("must be at least {min_length} characters long"));
+            }
+            if let Some(max_length) = node.max_length.filter(|max| length > *max) {
+                error(format!("must be at most {max_length} characters long"));
+            }
+            if let Some(pattern) = node.pattern.as_ref().filter(|p| !p.is_match(string)) {
+                error(format!("must match the pattern '{}'", pattern.as_str()));
+            }
+            if node.email && email_address::parse(string).is_err() {
+                error("must be a valid email address".to_string());
+            }
+        }
+        Value::Object(object) => {
+            for name in &node.required {
+                if !object.contains_key(name) {
+                    errors.push(ValidationError {
+                        path: format!("{path}/{}", escape_pointer(name)),
+                        message: "is required".to_string(),
+                    });
+                }
+            }
+            for (name, property) in &node.properties {
+                if let Some(value) = object.get(name) {
+                    let property_path = format!("{path}/{}", escape_pointer(name));
+                    validate_node(property, value, &property_path, errors);
+                }
+            }
+        }
+        Value::Array(values) => {
+            if let Some(items) = &node.items {
+                for (index, value) in values.iter().enumerate() {
+                    validate_node(items, value, &format!("{path}/{index}"), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("object", Value::Object(_))
+        | ("array", Value::Array(_))
+        | ("string", Value::String(_))
+        | ("number", Value::Number(_))
+        | ("boolean", Value::Bool(_))
+        | ("null", Value::Null) => true,
+        // 1.0 is an integer too
+        ("integer", Value::Number(number)) => {
+            number.is_i64() || number.is_u64() || number.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+/// Escapes a property name for a JSON pointer (RFC 6901).
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Schema {
+        Schema::compile(json!({
+            "type": "object",
+            "required": ["email", "message"],
+            "properties": {
+                "email": {"type": "string", "format": "email"},
+                "message": {"type": "string", "minLength": 10, "maxLength": 500},
+                "topic": {"enum": ["sales", "support"]},
+                "phone": {"type": ["string", "null"], "pattern": "^\\+?[0-9 ]+$"},
+                "data": {
+                    "type": "object",
+                    "required": ["a/b"],
+                    "properties": {
+                        "tags": {"type": "array", "items": {"type": "string", "maxLength": 3}},
+                        "age": {"type": "integer"},
+                    },
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    fn error(path: &str, message: &str) -> ValidationError {
+        ValidationError {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let body = json!({
+            "email": "john@example.com",
+            "message": "Hello there, how are you?",
+            "topic": "sales",
+            "phone": null,
+            "data": {"a/b": true, "tags": ["abc"], "age": 42.0},
+            "other": "not in the schema",
+        });
+        assert_eq!(schema().validate(&body), vec![]);
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let body = json!({
+            "email": "john@",
+            "message": "Hi",
+            "topic": "other",
+            "phone": "call me",
+            "data": {"tags": ["abc", "abcd", 1], "age": 4.2},
+        });
+        assert_eq!(
+            schema().validate(&body),
+            vec![
+                error("/data/a~1b", "is required"),
+                error("/data/age", "must be of type integer"),
+                error("/data/tags/1", "must be at most 3 characters long"),
+                error("/data/tags/2", "must be of type string"),
+                error("/email", "must be a valid email address"),
+                error("/message", "must be at least 10 characters long"),
+                error("/phone", "must match the pattern '^\\+?[0-9 ]+$'"),
+                error("/topic", "must be one of \"sales\", \"support\""),
+            ]
+        );
+
+        assert_eq!(
+            schema().validate(&json!({})),
+            vec![
+                error("/email", "is required"),
+                error("/message", "is required")
+            ]
+        );
+        assert_eq!(
+            schema().validate(&json!([])),
+            vec![error("", "must be of type object")]
+        );
+    }
+
+    #[test]
+    fn test_boolean_schemas() {
+        let schema = Schema::compile(json!({"properties": {"admin": false, "any": true}})).unwrap();
+        assert_eq!(
+            schema.validate(&json!({"admin": true, "any": 1})),
+            vec![error("/admin", "is not allowed")]
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let invalid = [
+            (
+                json!("string"),
+                "schema must be an object or a boolean at ''",
+            ),
+            (json!({"type": "strin"}), "unknown type 'strin' at ''"),
+            (
+                json!({"properties": {"name": {"minLength": -1}}}),
+                "'minLength' must be a non-negative integer at '/properties/name'",
+            ),
+            (
+                json!({"properties": {"name": {"pattern": "("}}}),
+                "invalid 'pattern' '(' at '/properties/name'",
+            ),
+            (
+                json!({"required": "name"}),
+                "'required' must be an array of strings at ''",
+            ),
+        ];
+        for (schema, message) in invalid {
+            assert_eq!(Schema::compile(schema).unwrap_err().to_string(), message);
+        }
+    }
+}
@@ -1,23 +1,80 @@
-const SENDGRID_ENDPOINT: &str = "https://api.sendgrid.com/v3/mail/send";
+use std::collections::BTreeMap;
+
+use crate::email::{base64_encode, Email};
+use crate::email_address::Mailbox;
+use crate::provider::{MailProvider, SendResult};
+use crate::transport::{HttpRequest, Timeouts, Transport};
+
+const MAIL_SEND_PATH: &str = "/v3/mail/send";
+
+/// Sends emails through SendGrid's v3 Mail Send API.
+pub struct SendGrid<'a> {
+    api_key: String,
+    base_url: String, // such as https://api.sendgrid.com, see `Settings::sendgrid_base_url`
+    transport: &'a dyn Transport,
+}
+
+impl<'a> SendGrid<'a> {
+    pub fn new(api_key: &str, base_url: &str, transport: &'a dyn Transport) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            base_url: base_url.to_string(),
+            transport,
+        }
+    }
+}
+
+impl MailProvider for SendGrid<'_> {
+    fn name(&self) -> &'static str {
+        "sendgrid"
+    }
+
+    fn send(&self, email: &Email, timeouts: Timeouts) -> SendResult {
+        let body = match serde_json::to_vec(&SendGridPayload::new(email)) {
+            Ok(body) => body,
+            Err(e) => return SendResult::internal_error(&e.into()),
+        };
+        let url = format!("{}{MAIL_SEND_PATH}", self.base_url);
+        let request = HttpRequest::json(&url, body, timeouts).bearer(&self.api_key);
+        match self.transport.post(&request) {
+            Ok(response) => SendResult::from_response(&response),
+            Err(e) => SendResult::transport_error(&e),
+        }
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct SendGridPayload {
     personalizations: Vec<SendGridPayloadPersonalizations>,
     from: SendGridPayloadEmail,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<SendGridPayloadEmail>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    reply_to_list: Vec<SendGridPayloadEmail>, // used instead of reply_to for several addresses
     #[serde(skip_serializing_if = "Vec::is_empty")]
     content: Vec<SendGridPayloadContent>, // used only if no template_id is provided via settings
     #[serde(skip_serializing_if = "Option::is_none")]
     template_id: Option<String>, // used only if provided via settings
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    attachments: Vec<SendGridPayloadAttachment>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    headers: BTreeMap<String, String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
 struct SendGridPayloadEmail {
     email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
 struct SendGridPayloadPersonalizations {
     to: Vec<SendGridPayloadEmail>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    cc: Vec<SendGridPayloadEmail>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    bcc: Vec<SendGridPayloadEmail>,
     #[serde(skip_serializing_if = "Option::is_none")]
     subject: Option<String>, // used if no template_id is provided
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -31,83 +88,139 @@ struct SendGridPayloadContent {
     value: String,
 }
 
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SendGridPayloadAttachment {
+    content: String, // base64 encoded
+    filename: String,
+    #[serde(rename = "type")]
+    _type: String,
+    disposition: String,
+}
+
+fn to_email(email: &str) -> SendGridPayloadEmail {
+    SendGridPayloadEmail {
+        email: email.to_string(),
+        name: None,
+    }
+}
+
+fn to_named_email(mailbox: &Mailbox) -> SendGridPayloadEmail {
+    SendGridPayloadEmail {
+        email: mailbox.email.clone(),
+        name: mailbox.name.clone(),
+    }
+}
+
+fn to_emails(addresses: &[String]) -> Vec<SendGridPayloadEmail> {
+    addresses.iter().map(|email| to_email(email)).collect()
+}
+
 impl SendGridPayload {
-    pub fn new(
-        email_from: String,
-        email_to: String,
-        subject: String,
-        message: Option<String>,
-        template_id: Option<String>,
-        dynamic_template_data: Option<serde_json::Value>,
-    ) -> Self {
-        if template_id.is_some() && !template_id.as_ref().unwrap().is_empty() {
-            // use template if provided
-            Self {
-                personalizations: vec![SendGridPayloadPersonalizations {
-                    to: vec![SendGridPayloadEmail { email: email_to }],
-                    subject: None, // subject is not used if template_id is provided
-                    dynamic_template_data,
-                }],
-                from: SendGridPayloadEmail { email: email_from },
-                content: vec![], // no content if template_id is provided
-                template_id,
-            }
-        } else {
-            // simple text message without template
-            Self {
-                personalizations: vec![SendGridPayloadPersonalizations {
-                    to: vec![SendGridPayloadEmail { email: email_to }],
-                    subject: Some(subject),
-                    dynamic_template_data: None,
-                }],
-                from: SendGridPayloadEmail { email: email_from },
-                content: vec![SendGridPayloadContent {
-                    _type: "text/plain".to_string(),
-                    value: message.unwrap().to_string(),
-                }],
-                template_id: None, // use content if no template_id is provided
+    pub fn new(email: &Email) -> Self {
+        let recipients = &email.recipients;
+        let mut personalization = SendGridPayloadPersonalizations {
+            to: to_emails(&recipients.to),
+            cc: to_emails(&recipients.cc),
+            bcc: to_emails(&recipients.bcc),
+            subject: None,
+            dynamic_template_data: None,
+        };
+        let mut content = vec![];
+        match (&email.template_id, &email.body) {
+            // subject and content are defined by the template
+            (Some(_), _) => personalization.dynamic_template_data = email.template_data.clone(),
+            (None, Some(body)) => {
+                personalization.subject = Some(email.subject.clone());
+                // SendGrid requires text/plain to come first, before text/html
+                content = vec![
+                    SendGridPayloadContent {
+                        _type: "text/plain".to_string(),
+                        value: body.text.clone(),
+                    },
+                    SendGridPayloadContent {
+                        _type: "text/html".to_string(),
+                        value: body.html.clone(),
+                    },
+                ];
             }
+            (None, None) => personalization.subject = Some(email.subject.clone()),
         }
-    }
 
-    pub fn send(&self, api_key: &str) -> anyhow::Result<waki::Response> {
-        let client = waki::Client::new();
-        let response = client
-            .post(SENDGRID_ENDPOINT)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {api_key}"))
-            .body(serde_json::to_vec(self)?)
-            .send()?;
-        Ok(response)
+        // SendGrid expects `reply_to_list` when there are several reply-to addresses
+        let (reply_to, reply_to_list) = match email.reply_to.as_slice() {
+            [mailbox] => (Some(to_named_email(mailbox)), vec![]),
+            mailboxes => (None, mailboxes.iter().map(to_named_email).collect()),
+        };
+
+        Self {
+            personalizations: vec![personalization],
+            from: to_named_email(&email.from),
+            reply_to,
+            reply_to_list,
+            content,
+            template_id: email.template_id.clone(),
+            attachments: email
+                .attachments
+                .iter()
+                .map(|attachment| SendGridPayloadAttachment {
+                    content: base64_encode(&attachment.content),
+                    filename: attachment.filename.clone(),
+                    _type: attachment.content_type.clone(),
+                    disposition: "attachment".to_string(),
+                })
+                .collect(),
+            headers: email
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::email::{Attachment, MessageBody, Recipients};
     use serde_json::json;
 
+    fn static_email(recipients: Recipients, subject: &str, body: MessageBody) -> Email {
+        Email::new(
+            "from@example.com".to_string(),
+            recipients,
+            subject.to_string(),
+            Some(body),
+            None,
+            None,
+        )
+    }
+
+    fn to(email: &str) -> Recipients {
+        Recipients {
+            to: vec![email.to_string()],
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_build_sendgrid_payload_with_template() {
-        let email_from = "from@example.com".to_string();
-        let email_to = "to@example.com".to_string();
-        let subject = "Ignored Subject".to_string();
-        let message = None;
         let template_id = Some("template-123".to_string());
         let dynamic_template_data = Some(json!({"name": "John"}));
 
-        let payload = SendGridPayload::new(
-            email_from.clone(),
-            email_to.clone(),
-            subject,
-            message,
+        let payload = SendGridPayload::new(&Email::new(
+            "from@example.com".to_string(),
+            to("to@example.com"),
+            "Ignored Subject".to_string(),
+            None,
             template_id.clone(),
             dynamic_template_data.clone(),
-        );
+        ));
 
-        assert_eq!(payload.from.email, email_from);
+        assert_eq!(payload.from.email, "from@example.com");
         assert_eq!(payload.personalizations.len(), 1);
-        assert_eq!(payload.personalizations[0].to[0].email, email_to);
+        assert_eq!(payload.personalizations[0].to[0].email, "to@example.com");
+        assert!(payload.personalizations[0].cc.is_empty());
+        assert!(payload.personalizations[0].bcc.is_empty());
         assert!(payload.personalizations[0].subject.is_none());
         assert_eq!(
             payload.personalizations[0].dynamic_template_data,
@@ -115,38 +228,180 @@ mod tests {
         );
         assert_eq!(payload.content.len(), 0);
         assert_eq!(payload.template_id, template_id);
-        assert_eq!(
-            payload.personalizations[0].dynamic_template_data,
-            dynamic_template_data
-        );
     }
 
     #[test]
     fn test_build_sendgrid_payload_with_static_content() {
-        let email_from = "from@example.com".to_string();
-        let email_to = "to@example.com".to_string();
-        let subject = "Hello".to_string();
-        let message = Some("This is a test message.".to_string());
-        let template_id = None;
-        let dynamic_template_data = None;
-
-        let payload = SendGridPayload::new(
-            email_from.clone(),
-            email_to.clone(),
-            subject.clone(),
-            message.clone(),
-            template_id,
-            dynamic_template_data,
-        );
+        let message = "This is a test message.".to_string();
+        let payload = SendGridPayload::new(&static_email(
+            to("to@example.com"),
+            "Hello",
+            MessageBody::from_text(message.clone()),
+        ));
 
-        assert_eq!(payload.from.email, email_from);
+        assert_eq!(payload.from.email, "from@example.com");
         assert_eq!(payload.personalizations.len(), 1);
-        assert_eq!(payload.personalizations[0].to[0].email, email_to);
-        assert_eq!(payload.personalizations[0].subject, Some(subject));
+        assert_eq!(payload.personalizations[0].to[0].email, "to@example.com");
+        assert_eq!(
+            payload.personalizations[0].subject,
+            Some("Hello".to_string())
+        );
         assert!(payload.personalizations[0].dynamic_template_data.is_none());
-        assert_eq!(payload.content.len(), 1);
+        assert_eq!(payload.content.len(), 2);
         assert_eq!(payload.content[0]._type, "text/plain");
-        assert_eq!(payload.content[0].value, message.unwrap());
+        assert_eq!(payload.content[0].value, message);
+        assert_eq!(payload.content[1]._type, "text/html");
+        assert_eq!(payload.content[1].value, message);
         assert!(payload.template_id.is_none());
     }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_cc_and_bcc() {
+        let recipients = Recipients {
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            cc: vec!["c@example.com".to_string()],
+            bcc: vec!["d@example.com".to_string()],
+        };
+        let email = static_email(
+            recipients,
+            "Hello",
+            MessageBody::from_text("Hi".to_string()),
+        );
+
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["personalizations"][0],
+            json!({
+                "to": [{"email": "a@example.com"}, {"email": "b@example.com"}],
+                "cc": [{"email": "c@example.com"}],
+                "bcc": [{"email": "d@example.com"}],
+                "subject": "Hello",
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_reply_to() {
+        let mut email = static_email(
+            to("owner@example.com"),
+            "Hello",
+            MessageBody::from_text("Hi".to_string()),
+        );
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert!(json.get("reply_to").is_none());
+
+        email.set_reply_to(vec![Mailbox {
+            email: "visitor@example.com".to_string(),
+            name: Some("John".to_string()),
+        }]);
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["reply_to"],
+            json!({"email": "visitor@example.com", "name": "John"})
+        );
+        assert!(json.get("reply_to_list").is_none());
+
+        email.set_reply_to(vec![
+            Mailbox {
+                email: "a@example.com".to_string(),
+                name: None,
+            },
+            Mailbox {
+                email: "b@example.com".to_string(),
+                name: Some("Support".to_string()),
+            },
+        ]);
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert!(json.get("reply_to").is_none());
+        assert_eq!(
+            json["reply_to_list"],
+            json!([
+                {"email": "a@example.com"},
+                {"email": "b@example.com", "name": "Support"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_from_name() {
+        let mut email = static_email(
+            to("owner@example.com"),
+            "Hello",
+            MessageBody::from_text("Hi".to_string()),
+        );
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(json["from"], json!({"email": "from@example.com"}));
+
+        email.set_from_name("John\r\nBcc: evil@example.com via Website".to_string());
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["from"],
+            json!({"email": "from@example.com", "name": "John Bcc: evil@example.com via Website"})
+        );
+
+        email.set_from_name(" ".to_string());
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(json["from"], json!({"email": "from@example.com"}));
+    }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_html_content() {
+        let email = static_email(
+            to("to@example.com"),
+            "Hello",
+            MessageBody::from_html("<p>Hi <b>there</b></p>".to_string()),
+        );
+
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["content"],
+            json!([
+                {"type": "text/plain", "value": "Hi there"},
+                {"type": "text/html", "value": "<p>Hi <b>there</b></p>"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_attachment() {
+        let mut email = static_email(
+            to("to@example.com"),
+            "Hello",
+            MessageBody::from_text("Hi".to_string()),
+        );
+        email.add_attachment(Attachment {
+            filename: "hello.txt".to_string(),
+            content_type: "text/plain".to_string(),
+            content: b"Hello, world!".to_vec(),
+        });
+
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["attachments"],
+            json!([{
+                "content": "SGVsbG8sIHdvcmxkIQ==",
+                "filename": "hello.txt",
+                "type": "text/plain",
+                "disposition": "attachment",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_build_sendgrid_payload_with_headers() {
+        let mut email = static_email(
+            to("to@example.com"),
+            "Hello",
+            MessageBody::from_text("Hi".to_string()),
+        );
+        email
+            .add_header("X-Source", "website\r\nBcc: victim@example.com")
+            .unwrap();
+
+        let json = serde_json::to_value(SendGridPayload::new(&email)).unwrap();
+        assert_eq!(
+            json["headers"],
+            json!({"X-Source": "website Bcc: victim@example.com"})
+        );
+    }
 }
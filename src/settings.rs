@@ -0,0 +1,1120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::email_address::{self, Mailbox};
+use crate::retry::RetryPolicy;
+use crate::schema::Schema;
+use crate::template::Template;
+use crate::template_fields::{self, TemplateFields, UnknownFields};
+
+const SETTINGS_HEADER: &str = "x-edgee-component-settings";
+const DEFAULT_SUBJECT: &str = "Contact request";
+const DEFAULT_ACK_SUBJECT: &str = "We received your message";
+// "default" body templates: a table of all submitted fields, in order
+const DEFAULT_BODY_TEMPLATE: &str = "{{#each this}}{{@key}}: {{this}}\n{{/each}}";
+const DEFAULT_BODY_TEMPLATE_HTML: &str = "<table cellpadding=\"4\">{{#each this}}<tr><th align=\"left\" valign=\"top\">{{@key}}</th><td style=\"white-space: pre-wrap\">{{this}}</td></tr>{{/each}}</table>";
+const DEFAULT_ATTACHMENTS_MAX_TOTAL_SIZE: usize = 20 * 1024 * 1024; // keeps the base64 encoded payload below SendGrid's 30MB limit
+pub const BODY_HEADROOM: usize = 1024 * 1024; // for fields and multipart boundaries, on top of attachments
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+const MAX_RETRY_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_RETRY_BASE_DELAY: u64 = 500; // in milliseconds
+const DEFAULT_CONNECT_TIMEOUT: u64 = 5_000; // in milliseconds
+const DEFAULT_REQUEST_TIMEOUT: u64 = 8_000; // in milliseconds, leaves time to retry or fail over
+const DEFAULT_TIMEOUT: u64 = 20_000; // in milliseconds, below the platform's own limit
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipientMode {
+    /// the email is sent to the address(es) provided in the request body
+    #[default]
+    Visitor,
+    /// the email is sent to `email_to` only, the visitor's address becomes reply-to
+    Fixed,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, PartialEq)]
+pub enum ContentType {
+    /// `message` is plain text, the HTML part is generated from it
+    #[default]
+    #[serde(rename = "text/plain")]
+    Text,
+    /// `message` is HTML, the plain text part is generated from it
+    #[serde(rename = "text/html")]
+    Html,
+}
+
+/// SendGrid data residency, which determines the API host.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    #[default]
+    Global,
+    /// for EU subusers, whose data stays in the EU
+    Eu,
+}
+
+impl Region {
+    fn base_url(&self) -> &'static str {
+        match self {
+            Region::Global => "https://api.sendgrid.com",
+            Region::Eu => "https://api.eu.sendgrid.com",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    pub api_key: String,
+    pub email_from: String,
+    pub email_from_name: Option<String>, // from a "Name <address>" email_from, the sender name unless from_name is set
+    pub subject: Template, // optional, such as "New contact from {{name}}", defaults to "Contact request"
+    pub template_id: Option<String>, // optional
+    pub cc: Vec<String>,   // optional, comma-separated in settings
+    pub bcc: Vec<String>,  // optional, comma-separated in settings
+    pub recipient_mode: RecipientMode, // optional, defaults to "visitor"
+    pub email_to: Vec<String>, // required in "fixed" mode, comma-separated in settings
+    pub ack_template_id: Option<String>, // optional, enables the acknowledgement email
+    pub ack_subject: Template, // optional, defaults to "We received your message"
+    pub ack_message: Option<String>, // optional, enables the acknowledgement email
+    pub content_type: ContentType, // optional, defaults to "text/plain"
+    pub attachments_allowed_types: Vec<String>, // optional, attachments are rejected if empty
+    pub attachments_max_file_size: usize, // optional, in bytes, defaults to the total size
+    pub attachments_max_total_size: usize, // optional, in bytes, defaults to 20MB
+    pub success_redirect: Option<String>, // optional, redirects with 303 instead of JSON
+    pub error_redirect: Option<String>, // optional, redirects with 303 and an `error` query parameter
+    pub allowed_origins: Vec<String>, // optional, comma-separated origins allowed to call the endpoint (CORS)
+    pub health_check: bool, // optional, enables GET requests returning the status and a summary of the configuration
+    pub custom_headers: BTreeMap<String, String>, // optional, JSON object of headers added to the notification
+    pub from_name: Option<Template>, // optional, sender name such as "{{name}} via Website"
+    pub reply_to: Vec<Mailbox>, // optional, comma-separated, replaces the visitor's address in "fixed" mode
+    pub reply_to_name: Option<Template>, // optional, name of the visitor's reply-to address in "fixed" mode
+    pub allow_subject_override: bool, // optional, lets the request body's `subject` field replace the subject
+    pub body_template: Option<Template>, // optional, plain text body rendered from the request fields
+    pub body_template_html: Option<Template>, // optional, HTML body rendered from the request fields (escaped)
+    pub template_fields: Option<TemplateFields>, // optional, JSON object of the `data` fields passed to templates
+    pub template_unknown_fields: UnknownFields, // optional, "drop" (default) or "reject" fields not listed in `template_fields`
+    pub schema: Option<Schema>, // optional, JSON Schema every request body is validated against
+    pub debug: bool,            // optional, includes SendGrid's error details in error responses
+    pub retry_max_attempts: u32, // optional, attempts for transient SendGrid failures (429 and 5xx), defaults to 3
+    pub retry_base_delay: u64, // optional, in milliseconds, doubled after each attempt, defaults to 500
+    pub connect_timeout: u64, // optional, in milliseconds, to connect to SendGrid, defaults to 5000
+    pub request_timeout: u64, // optional, in milliseconds, for each attempt, defaults to 8000
+    pub timeout: u64, // optional, in milliseconds, to send each email, retries included, defaults to 20000
+    pub region: Region, // optional, "global" (default) or "eu"
+    pub api_base_url: Option<String>, // optional, overrides the SendGrid API host, such as a mock server in tests
+    pub test_mode: bool, // optional, allows plain http API URLs, never enable it in production
+    pub failover_url: Option<String>, // optional, https endpoint of the secondary provider, used when SendGrid is unavailable
+    pub failover_api_key: Option<String>, // optional, sent as a bearer token to the secondary provider
+}
+
+impl Settings {
+    /// Largest request body read, so oversized uploads are rejected before being buffered.
+    pub fn max_body_size(&self) -> usize {
+        self.attachments_max_total_size
+            .saturating_add(BODY_HEADROOM)
+    }
+
+    /// The acknowledgement is sent only if it has some content.
+    pub fn ack_enabled(&self) -> bool {
+        self.ack_template_id.is_some() || self.ack_message.is_some()
+    }
+
+    /// The SendGrid API host, without trailing slash.
+    pub fn sendgrid_base_url(&self) -> &str {
+        self.api_base_url
+            .as_deref()
+            .unwrap_or(self.region.base_url())
+            .trim_end_matches('/')
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_max_attempts,
+            base_delay: Duration::from_millis(self.retry_base_delay),
+            connect_timeout: Duration::from_millis(self.connect_timeout),
+            request_timeout: Duration::from_millis(self.request_timeout),
+            timeout: Duration::from_millis(self.timeout),
+        }
+    }
+
+    pub fn new(headers: &HashMap<String, Vec<String>>) -> anyhow::Result<Self> {
+        let settings = headers
+            .get(SETTINGS_HEADER)
+            .ok_or_else(|| anyhow::anyhow!("Missing '{SETTINGS_HEADER}' header"))?;
+
+        if settings.len() != 1 {
+            return Err(anyhow::anyhow!(
+                "Expected exactly one '{SETTINGS_HEADER}' header, found {}",
+                settings.len()
+            ));
+        }
+        Self::from_json(&settings[0])
+    }
+
+    /// Reads `allowed_origins` alone, for responses to requests whose settings are invalid,
+    /// so that browsers can still read the error. Empty if it can't be read either.
+    pub fn allowed_origins(headers: &HashMap<String, Vec<String>>) -> Vec<String> {
+        headers
+            .get(SETTINGS_HEADER)
+            .filter(|settings| settings.len() == 1)
+            .and_then(|settings| serde_json::from_str(&settings[0]).ok())
+            .and_then(|values| Values(values).list("allowed_origins").ok())
+            .unwrap_or_default()
+    }
+
+    /// Parses the settings JSON object. Values can be strings, as set in the console,
+    /// or the equivalent JSON types (booleans, numbers, arrays of strings).
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let values: Map<String, Value> = serde_json::from_str(json)
+            .map_err(|_| anyhow::anyhow!("Settings must be a JSON object"))?;
+        let values = Values(values);
+
+        let api_key = values.required_string("api_key")?;
+
+        let email_from = match values.mailboxes("email_from")?.as_slice() {
+            [] => {
+                return Err(invalid_setting(
+                    "email_from",
+                    "Missing required 'email_from' setting".to_string(),
+                ))
+            }
+            [email_from] => email_from.clone(),
+            _ => {
+                return Err(invalid_setting(
+                    "email_from",
+                    "Expected a single address in 'email_from' setting".to_string(),
+                ))
+            }
+        };
+
+        let recipient_mode = values.choice("recipient_mode", "'visitor' or 'fixed'")?;
+        let email_to = values.addresses("email_to")?;
+        if recipient_mode == RecipientMode::Fixed && email_to.is_empty() {
+            return Err(invalid_setting(
+                "email_to",
+                "Missing 'email_to' setting, required when 'recipient_mode' is 'fixed'".to_string(),
+            ));
+        }
+
+        // the default text template comes with the default HTML one, unless it is set too
+        let body_template = values.body_template("body_template", DEFAULT_BODY_TEMPLATE)?;
+        let body_template_html =
+            match values.body_template("body_template_html", DEFAULT_BODY_TEMPLATE_HTML)? {
+                None if values.string("body_template")?.as_deref() == Some("default") => {
+                    Some(Template::parse(DEFAULT_BODY_TEMPLATE_HTML)?)
+                }
+                template => template,
+            };
+
+        let attachments_max_total_size = values
+            .size("attachments_max_total_size")?
+            .unwrap_or(DEFAULT_ATTACHMENTS_MAX_TOTAL_SIZE);
+
+        let retry_max_attempts = values
+            .number("retry_max_attempts", "a number of attempts")?
+            .map_or(Ok(DEFAULT_RETRY_MAX_ATTEMPTS), |attempts| {
+                u32::try_from(attempts)
+                    .ok()
+                    .filter(|attempts| (1..=MAX_RETRY_MAX_ATTEMPTS).contains(attempts))
+                    .ok_or_else(|| {
+                        invalid_setting(
+                            "retry_max_attempts",
+                            format!("Invalid 'retry_max_attempts' setting {attempts}, expected 1 to {MAX_RETRY_MAX_ATTEMPTS} attempts"),
+                        )
+                    })
+            })?;
+
+        let region = values.choice("region", "'global' or 'eu'")?;
+        let test_mode = values.bool("test_mode")?;
+        let api_base_url = values.api_url("api_base_url", test_mode)?;
+        if api_base_url.is_some() && values.get("region").is_some() {
+            return Err(invalid_setting(
+                "api_base_url",
+                "Expected either 'region' or 'api_base_url' setting, not both".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            api_key,
+            email_from: email_from.email,
+            email_from_name: email_from.name,
+            subject: values
+                .template("subject")?
+                .unwrap_or(Template::parse(DEFAULT_SUBJECT)?),
+            template_id: values.string("template_id")?,
+            cc: values.addresses("cc")?,
+            bcc: values.addresses("bcc")?,
+            recipient_mode,
+            email_to,
+            ack_template_id: values.string("ack_template_id")?,
+            ack_subject: values
+                .template("ack_subject")?
+                .unwrap_or(Template::parse(DEFAULT_ACK_SUBJECT)?),
+            ack_message: values.string("ack_message")?,
+            content_type: values.choice("content_type", "'text/plain' or 'text/html'")?,
+            attachments_allowed_types: values.list("attachments_allowed_types")?,
+            attachments_max_file_size: values
+                .size("attachments_max_file_size")?
+                .unwrap_or(attachments_max_total_size),
+            attachments_max_total_size,
+            success_redirect: values.redirect("success_redirect")?,
+            error_redirect: values.redirect("error_redirect")?,
+            allowed_origins: values.list("allowed_origins")?,
+            health_check: values.bool("health_check")?,
+            custom_headers: values.headers("custom_headers")?,
+            from_name: values.template("from_name")?,
+            reply_to: values.mailboxes("reply_to")?,
+            reply_to_name: values.template("reply_to_name")?,
+            allow_subject_override: values.bool("allow_subject_override")?,
+            body_template,
+            body_template_html,
+            template_fields: values.template_fields("template_fields")?,
+            template_unknown_fields: values
+                .choice("template_unknown_fields", "'drop' or 'reject'")?,
+            schema: values.schema("schema")?,
+            debug: values.bool("debug")?,
+            retry_max_attempts,
+            retry_base_delay: values
+                .number("retry_base_delay", "a number of milliseconds")?
+                .unwrap_or(DEFAULT_RETRY_BASE_DELAY),
+            connect_timeout: values.timeout("connect_timeout", DEFAULT_CONNECT_TIMEOUT)?,
+            request_timeout: values.timeout("request_timeout", DEFAULT_REQUEST_TIMEOUT)?,
+            timeout: values.timeout("timeout", DEFAULT_TIMEOUT)?,
+            region,
+            api_base_url,
+            test_mode,
+            failover_url: values.api_url("failover_url", test_mode)?,
+            failover_api_key: values.string("failover_api_key")?,
+        })
+    }
+}
+
+/// Raw settings values, read with the expected type. Errors always name the offending key.
+struct Values(Map<String, Value>);
+
+impl Values {
+    /// Missing keys, `null` and empty strings are all treated as unset.
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key).filter(|value| match value {
+            Value::Null => false,
+            Value::String(value) => !value.trim().is_empty(),
+            _ => true,
+        })
+    }
+
+    fn invalid(&self, key: &str, expected: &str) -> anyhow::Error {
+        match self.get(key) {
+            Some(Value::String(value)) => invalid_setting(
+                key,
+                format!("Invalid '{key}' setting '{value}', expected {expected}"),
+            ),
+            Some(value) => invalid_setting(
+                key,
+                format!("Invalid '{key}' setting {value}, expected {expected}"),
+            ),
+            None => invalid_setting(key, format!("Invalid '{key}' setting, expected {expected}")),
+        }
+    }
+
+    fn string(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.trim().to_string())),
+            Some(Value::Number(value)) => Ok(Some(value.to_string())),
+            Some(_) => Err(self.invalid(key, "a string")),
+        }
+    }
+
+    fn required_string(&self, key: &str) -> anyhow::Result<String> {
+        self.string(key)?
+            .ok_or_else(|| invalid_setting(key, format!("Missing required '{key}' setting")))
+    }
+
+    /// A comma-separated string, or an array of strings.
+    fn list(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        self.items(key, split_list)
+    }
+
+    /// Validated addresses, as a comma-separated string or an array of strings.
+    fn addresses(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        let mailboxes = self.mailboxes(key)?;
+        Ok(mailboxes.into_iter().map(|mailbox| mailbox.email).collect())
+    }
+
+    /// Same as [`Values::addresses`], keeping display names.
+    fn mailboxes(&self, key: &str) -> anyhow::Result<Vec<Mailbox>> {
+        self.items(key, email_address::split_list)?
+            .iter()
+            .map(|email| {
+                email_address::parse_mailbox(email).map_err(|e| {
+                    invalid_setting(
+                        key,
+                        format!("Invalid email address '{email}' in '{key}' setting: {e}"),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn template(&self, key: &str) -> anyhow::Result<Option<Template>> {
+        match self.string(key)? {
+            None => Ok(None),
+            Some(template) => Template::parse(&template).map(Some).map_err(|e| {
+                invalid_setting(key, format!("Invalid '{key}' setting '{template}': {e}"))
+            }),
+        }
+    }
+
+    fn items(&self, key: &str, split: fn(&str) -> Vec<String>) -> anyhow::Result<Vec<String>> {
+        match self.get(key) {
+            None => Ok(vec![]),
+            Some(Value::String(value)) => Ok(split(value)),
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => Ok(value.trim().to_string()),
+                    _ => Err(self.invalid(key, "a list of strings")),
+                })
+                .filter(|item| !item.as_ref().is_ok_and(String::is_empty))
+                .collect(),
+            Some(_) => Err(self.invalid(key, "a list of strings")),
+        }
+    }
+
+    fn bool(&self, key: &str) -> anyhow::Result<bool> {
+        match self.get(key) {
+            None => Ok(false),
+            Some(Value::Bool(value)) => Ok(*value),
+            Some(Value::String(value)) if value.trim() == "true" => Ok(true),
+            Some(Value::String(value)) if value.trim() == "false" => Ok(false),
+            Some(_) => Err(self.invalid(key, "'true' or 'false'")),
+        }
+    }
+
+    fn size(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        self.number(key, "a number of bytes")?
+            .map(|size| usize::try_from(size).map_err(|_| self.invalid(key, "a number of bytes")))
+            .transpose()
+    }
+
+    /// A non-negative integer, as a JSON number or a string.
+    fn number(&self, key: &str, expected: &str) -> anyhow::Result<Option<u64>> {
+        let number = match self.get(key) {
+            None => return Ok(None),
+            Some(Value::Number(value)) => value.as_u64(),
+            Some(Value::String(value)) => value.trim().parse().ok(),
+            Some(_) => None,
+        };
+        number.map(Some).ok_or_else(|| self.invalid(key, expected))
+    }
+
+    /// A positive number of milliseconds.
+    fn timeout(&self, key: &str, default: u64) -> anyhow::Result<u64> {
+        let expected = "a positive number of milliseconds";
+        match self.number(key, expected)? {
+            None => Ok(default),
+            Some(0) => Err(self.invalid(key, expected)),
+            Some(timeout) => Ok(timeout),
+        }
+    }
+
+    /// One of the values of an enum, which defaults when unset.
+    fn choice<T>(&self, key: &str, expected: &str) -> anyhow::Result<T>
+    where
+        T: serde::de::DeserializeOwned + Default,
+    {
+        match self.get(key) {
+            None => Ok(T::default()),
+            Some(value) => {
+                serde_json::from_value(value.clone()).map_err(|_| self.invalid(key, expected))
+            }
+        }
+    }
+
+    /// Redirect URLs must be absolute http(s) URLs or absolute paths.
+    fn redirect(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let expected = "an http(s) URL or an absolute path";
+        match self.string(key).map_err(|_| self.invalid(key, expected))? {
+            None => Ok(None),
+            Some(url)
+                if (url.starts_with("https://")
+                    || url.starts_with("http://")
+                    || url.starts_with('/'))
+                    && !url.starts_with("//")
+                    && !url.contains(['\r', '\n']) =>
+            {
+                Ok(Some(url))
+            }
+            Some(_) => Err(self.invalid(key, expected)),
+        }
+    }
+
+    /// API endpoints must be https URLs, as they receive credentials, unless `allow_http` is set (test mode).
+    fn api_url(&self, key: &str, allow_http: bool) -> anyhow::Result<Option<String>> {
+        let (expected, schemes): (_, &[&str]) = match allow_http {
+            false => ("an https URL", &["https://"]),
+            true => ("an http(s) URL", &["https://", "http://"]),
+        };
+        match self.string(key).map_err(|_| self.invalid(key, expected))? {
+            None => Ok(None),
+            Some(url)
+                if schemes.iter().any(|scheme| {
+                    url.strip_prefix(scheme)
+                        .is_some_and(|host| !host.is_empty() && !host.starts_with('/'))
+                }) && !url.contains(char::is_whitespace) =>
+            {
+                Ok(Some(url))
+            }
+            Some(_) => Err(self.invalid(key, expected)),
+        }
+    }
+
+    /// Same as [`Values::template`], where `default` stands for a built-in template.
+    fn body_template(&self, key: &str, default: &str) -> anyhow::Result<Option<Template>> {
+        match self.string(key)?.as_deref() {
+            Some("default") => Template::parse(default).map(Some),
+            _ => self.template(key),
+        }
+    }
+
+    /// Field definitions such as `{"name": {"required": true}}`, as a JSON object or its JSON encoding.
+    fn template_fields(&self, key: &str) -> anyhow::Result<Option<TemplateFields>> {
+        let fields = match self.get(key) {
+            None => return Ok(None),
+            Some(Value::String(value)) => serde_json::from_str(value),
+            Some(value) => serde_json::from_value(value.clone()),
+        };
+        let invalid = |e: &dyn std::fmt::Display| {
+            invalid_setting(key, format!("Invalid '{key}' setting: {e}"))
+        };
+        let fields = fields.map_err(|e| invalid(&e))?;
+        template_fields::validate(&fields).map_err(|e| invalid(&e))?;
+        Ok(Some(fields))
+    }
+
+    /// A JSON Schema, as a JSON object or its JSON encoding.
+    fn schema(&self, key: &str) -> anyhow::Result<Option<Schema>> {
+        let schema = match self.get(key) {
+            None => return Ok(None),
+            Some(Value::String(value)) => serde_json::from_str(value).map_err(|_| {
+                invalid_setting(
+                    key,
+                    format!("Invalid '{key}' setting, expected a JSON Schema"),
+                )
+            })?,
+            Some(value) => value.clone(),
+        };
+        Schema::compile(schema)
+            .map(Some)
+            .map_err(|e| invalid_setting(key, format!("Invalid '{key}' setting: {e}")))
+    }
+
+    /// A JSON object of string values, or its JSON encoding as a string.
+    fn headers(&self, key: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        let invalid = || {
+            invalid_setting(
+                key,
+                format!("Invalid '{key}' setting, expected a JSON object of string values"),
+            )
+        };
+        match self.get(key) {
+            None => Ok(BTreeMap::new()),
+            Some(Value::String(value)) => serde_json::from_str(value).map_err(|_| invalid()),
+            Some(value) => serde_json::from_value(value.clone()).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// An error about a single setting. Its message can include the value, which may be a secret
+/// (such as an API key pasted in the wrong field), so only `key` should be shown to visitors.
+#[derive(Debug)]
+pub struct InvalidSetting {
+    pub key: String,
+    message: String,
+}
+
+impl std::fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for InvalidSetting {}
+
+fn invalid_setting(key: &str, message: String) -> anyhow::Error {
+    InvalidSetting {
+        key: key.to_string(),
+        message,
+    }
+    .into()
+}
+
+/// Splits a comma-separated settings value, ignoring empty entries.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &str = r#""api_key": "test_value", "email_from": "from@example.com""#;
+
+    fn settings_with(settings: &str) -> anyhow::Result<Settings> {
+        Settings::from_json(&format!("{{{REQUIRED}, {settings}}}"))
+    }
+
+    #[test]
+    fn test_settings_new() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-edgee-component-settings".to_string(),
+            vec![r#"{"api_key": "test_value", "email_from": "from@example.com"}"#.to_string()],
+        );
+
+        let settings = Settings::new(&headers).unwrap();
+        assert_eq!(settings.api_key, "test_value");
+        assert_eq!(settings.email_from, "from@example.com");
+        assert_eq!(settings.email_from_name, None);
+        assert_eq!(
+            settings.subject,
+            Template::parse("Contact request").unwrap()
+        );
+        assert_eq!(settings.template_id, None);
+        assert!(settings.cc.is_empty());
+        assert!(settings.bcc.is_empty());
+        assert!(!settings.health_check);
+        assert!(!settings.debug);
+    }
+
+    #[test]
+    fn test_settings_new_missing_header() {
+        let headers = HashMap::new();
+        let result = Settings::new(&headers);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing 'x-edgee-component-settings' header"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_multiple_headers() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-edgee-component-settings".to_string(),
+            vec![
+                r#"{"api_key": "test_value"}"#.to_string(),
+                r#"{"api_key": "another_value"}"#.to_string(),
+            ],
+        );
+        let result = Settings::new(&headers);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Expected exactly one 'x-edgee-component-settings' header"));
+    }
+
+    #[test]
+    fn test_settings_new_invalid_json() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-edgee-component-settings".to_string(),
+            vec!["not a json".to_string()],
+        );
+        let result = Settings::new(&headers);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Settings must be a JSON object"
+        );
+    }
+
+    #[test]
+    fn test_settings_required() {
+        let result = Settings::from_json(r#"{"email_from": "from@example.com"}"#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing required 'api_key' setting"
+        );
+
+        let result = Settings::from_json(r#"{"api_key": " ", "email_from": "from@example.com"}"#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing required 'api_key' setting"
+        );
+
+        let result = Settings::from_json(r#"{"api_key": "test_value", "email_from": null}"#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing required 'email_from' setting"
+        );
+    }
+
+    #[test]
+    fn test_settings_empty_values() {
+        let settings = settings_with(
+            r#""template_id": "", "ack_message": " ", "ack_template_id": null, "subject": "", "cc": "", "health_check": """#,
+        )
+        .unwrap();
+        assert_eq!(settings.template_id, None);
+        assert_eq!(settings.ack_message, None);
+        assert_eq!(settings.ack_template_id, None);
+        assert!(!settings.ack_enabled());
+        assert_eq!(
+            settings.subject,
+            Template::parse("Contact request").unwrap()
+        );
+        assert!(settings.cc.is_empty());
+        assert!(!settings.health_check);
+    }
+
+    #[test]
+    fn test_settings_typed_values() {
+        let settings = settings_with(
+            r#""health_check": true, "attachments_max_file_size": 1024, "cc": ["a@example.com", "b@example.com"], "allowed_origins": ["https://www.example.com"], "custom_headers": {"X-Source": "website"}"#,
+        )
+        .unwrap();
+        assert!(settings.health_check);
+        assert_eq!(settings.attachments_max_file_size, 1024);
+        assert_eq!(settings.cc, vec!["a@example.com", "b@example.com"]);
+        assert_eq!(settings.allowed_origins, vec!["https://www.example.com"]);
+        assert_eq!(
+            settings.custom_headers,
+            BTreeMap::from([("X-Source".to_string(), "website".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_settings_invalid_types() {
+        let invalid = [
+            (
+                r#""subject": ["a", "b"]"#,
+                r#"Invalid 'subject' setting ["a","b"], expected a string"#,
+            ),
+            (
+                r#""health_check": 1"#,
+                "Invalid 'health_check' setting 1, expected 'true' or 'false'",
+            ),
+            (
+                r#""cc": [42]"#,
+                "Invalid 'cc' setting [42], expected a list of strings",
+            ),
+            (
+                r#""attachments_max_total_size": -1"#,
+                "Invalid 'attachments_max_total_size' setting -1, expected a number of bytes",
+            ),
+            (
+                r#""success_redirect": true"#,
+                "Invalid 'success_redirect' setting true, expected an http(s) URL or an absolute path",
+            ),
+        ];
+        for (settings, error) in invalid {
+            assert_eq!(
+                settings_with(settings).unwrap_err().to_string(),
+                error,
+                "{settings}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_new_cc_bcc() {
+        let settings =
+            settings_with(r#""cc": "a@example.com, b@example.com,", "bcc": "c@example.com""#)
+                .unwrap();
+        assert_eq!(settings.cc, vec!["a@example.com", "b@example.com"]);
+        assert_eq!(settings.bcc, vec!["c@example.com"]);
+    }
+
+    #[test]
+    fn test_settings_new_fixed_mode() {
+        let settings =
+            settings_with(r#""recipient_mode": "fixed", "email_to": "owner@example.com""#).unwrap();
+        assert_eq!(settings.recipient_mode, RecipientMode::Fixed);
+        assert_eq!(settings.email_to, vec!["owner@example.com"]);
+    }
+
+    #[test]
+    fn test_settings_new_fixed_mode_missing_email_to() {
+        let result = settings_with(r#""recipient_mode": "fixed""#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Missing 'email_to' setting, required when 'recipient_mode' is 'fixed'"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_invalid_recipient_mode() {
+        let result = settings_with(r#""recipient_mode": "open""#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid 'recipient_mode' setting 'open', expected 'visitor' or 'fixed'"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_invalid_content_type() {
+        let result = settings_with(r#""content_type": "text/markdown""#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid 'content_type' setting 'text/markdown', expected 'text/plain' or 'text/html'"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_invalid_size() {
+        let result = settings_with(r#""attachments_max_file_size": "1MB""#);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid 'attachments_max_file_size' setting '1MB', expected a number of bytes"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_redirects() {
+        let settings = settings_with(
+            r#""success_redirect": "https://example.com/thanks", "error_redirect": "/contact""#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.success_redirect,
+            Some("https://example.com/thanks".to_string())
+        );
+        assert_eq!(settings.error_redirect, Some("/contact".to_string()));
+
+        let settings = settings_with(r#""success_redirect": """#).unwrap();
+        assert_eq!(settings.success_redirect, None);
+    }
+
+    #[test]
+    fn test_settings_new_invalid_redirect() {
+        for url in ["javascript:alert(1)", "//evil.example.com", "thanks.html"] {
+            let result = settings_with(&format!(r#""error_redirect": "{url}""#));
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!("Invalid 'error_redirect' setting '{url}', expected an http(s) URL or an absolute path")
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_new_retry() {
+        let settings = settings_with(r#""debug": false"#).unwrap();
+        assert_eq!(
+            settings.retry_policy(),
+            RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(500),
+                connect_timeout: Duration::from_secs(5),
+                request_timeout: Duration::from_secs(8),
+                timeout: Duration::from_secs(20),
+            }
+        );
+
+        let settings =
+            settings_with(r#""retry_max_attempts": "1", "retry_base_delay": 200"#).unwrap();
+        assert_eq!(settings.retry_max_attempts, 1);
+        assert_eq!(settings.retry_base_delay, 200);
+
+        let settings =
+            settings_with(r#""connect_timeout": 1000, "request_timeout": 3000, "timeout": "8000""#)
+                .unwrap();
+        assert_eq!(
+            (
+                settings.connect_timeout,
+                settings.request_timeout,
+                settings.timeout
+            ),
+            (1000, 3000, 8000)
+        );
+
+        for (fragment, error) in [
+            (
+                r#""retry_max_attempts": 0"#,
+                "Invalid 'retry_max_attempts' setting 0, expected 1 to 10 attempts",
+            ),
+            (
+                r#""retry_max_attempts": "many""#,
+                "Invalid 'retry_max_attempts' setting 'many', expected a number of attempts",
+            ),
+            (
+                r#""retry_base_delay": 0.5"#,
+                "Invalid 'retry_base_delay' setting 0.5, expected a number of milliseconds",
+            ),
+            (
+                r#""timeout": 0"#,
+                "Invalid 'timeout' setting 0, expected a positive number of milliseconds",
+            ),
+            (
+                r#""request_timeout": -1"#,
+                "Invalid 'request_timeout' setting -1, expected a positive number of milliseconds",
+            ),
+            (
+                r#""connect_timeout": "fast""#,
+                "Invalid 'connect_timeout' setting 'fast', expected a positive number of milliseconds",
+            ),
+        ] {
+            assert_eq!(settings_with(fragment).unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_settings_new_failover() {
+        let settings = settings_with(r#""debug": false"#).unwrap();
+        assert_eq!(settings.failover_url, None);
+
+        let settings = settings_with(
+            r#""failover_url": "https://relay.example.com/send", "failover_api_key": "secret""#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.failover_url.as_deref(),
+            Some("https://relay.example.com/send")
+        );
+        assert_eq!(settings.failover_api_key.as_deref(), Some("secret"));
+
+        for url in ["http://relay.example.com", "relay.example.com", "https://"] {
+            let result = settings_with(&format!(r#""failover_url": "{url}""#));
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!("Invalid 'failover_url' setting '{url}', expected an https URL")
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_new_api_base_url() {
+        let settings = settings_with(r#""debug": false"#).unwrap();
+        assert_eq!(settings.region, Region::Global);
+        assert_eq!(settings.sendgrid_base_url(), "https://api.sendgrid.com");
+
+        let settings = settings_with(r#""region": "eu""#).unwrap();
+        assert_eq!(settings.sendgrid_base_url(), "https://api.eu.sendgrid.com");
+
+        let settings = settings_with(r#""api_base_url": "https://sendgrid.example.com/""#).unwrap();
+        assert_eq!(settings.sendgrid_base_url(), "https://sendgrid.example.com");
+
+        // plain http is only allowed for mock servers, in test mode
+        let settings =
+            settings_with(r#""api_base_url": "http://localhost:8080", "test_mode": true"#).unwrap();
+        assert_eq!(settings.sendgrid_base_url(), "http://localhost:8080");
+
+        for (fragment, error) in [
+            (
+                r#""region": "us""#,
+                "Invalid 'region' setting 'us', expected 'global' or 'eu'",
+            ),
+            (
+                r#""api_base_url": "http://localhost:8080""#,
+                "Invalid 'api_base_url' setting 'http://localhost:8080', expected an https URL",
+            ),
+            (
+                r#""api_base_url": "ftp://localhost", "test_mode": true"#,
+                "Invalid 'api_base_url' setting 'ftp://localhost', expected an http(s) URL",
+            ),
+            (
+                r#""api_base_url": "https://sendgrid.example.com", "region": "eu""#,
+                "Expected either 'region' or 'api_base_url' setting, not both",
+            ),
+        ] {
+            assert_eq!(settings_with(fragment).unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_settings_new_allowed_origins() {
+        let settings =
+            settings_with(r#""allowed_origins": "https://www.example.com, https://*.example.com""#)
+                .unwrap();
+        assert_eq!(
+            settings.allowed_origins,
+            vec!["https://www.example.com", "https://*.example.com"]
+        );
+
+        // still read when other settings are invalid
+        let headers = HashMap::from([(
+            "x-edgee-component-settings".to_string(),
+            vec![r#"{"api_key": "", "allowed_origins": ["https://www.example.com"]}"#.to_string()],
+        )]);
+        assert!(Settings::new(&headers).is_err());
+        assert_eq!(
+            Settings::allowed_origins(&headers),
+            vec!["https://www.example.com"]
+        );
+        assert!(Settings::allowed_origins(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_settings_new_health_check() {
+        let settings = settings_with(r#""health_check": "true""#).unwrap();
+        assert!(settings.health_check);
+
+        assert_eq!(
+            settings_with(r#""health_check": "yes""#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'health_check' setting 'yes', expected 'true' or 'false'"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_custom_headers() {
+        let settings = settings_with(r#""custom_headers": "{\"X-Source\": \"website\"}""#).unwrap();
+        assert_eq!(
+            settings.custom_headers,
+            BTreeMap::from([("X-Source".to_string(), "website".to_string())])
+        );
+
+        assert_eq!(
+            settings_with(r#""custom_headers": "X-Source: website""#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'custom_headers' setting, expected a JSON object of string values"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_names() {
+        let settings = settings_with(
+            r#""from_name": "{{name}} via Website", "reply_to": "Support <support@example.com>, sales@example.com""#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.from_name,
+            Some(Template::parse("{{name}} via Website").unwrap())
+        );
+        assert_eq!(
+            settings.reply_to,
+            vec![
+                Mailbox {
+                    email: "support@example.com".to_string(),
+                    name: Some("Support".to_string()),
+                },
+                Mailbox {
+                    email: "sales@example.com".to_string(),
+                    name: None,
+                },
+            ]
+        );
+        assert_eq!(settings.reply_to_name, None);
+
+        let settings = Settings::from_json(
+            r#"{"api_key": "test_value", "email_from": "Acme <from@example.com>"}"#,
+        )
+        .unwrap();
+        assert_eq!(settings.email_from, "from@example.com");
+        assert_eq!(settings.email_from_name.as_deref(), Some("Acme"));
+
+        assert_eq!(
+            settings_with(r#""reply_to_name": "{{name""#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'reply_to_name' setting '{{name': unterminated '{{' placeholder"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_body_template() {
+        let settings = settings_with(r#""body_template": "default""#).unwrap();
+        assert_eq!(
+            settings.body_template,
+            Some(Template::parse(DEFAULT_BODY_TEMPLATE).unwrap())
+        );
+        assert_eq!(
+            settings.body_template_html,
+            Some(Template::parse(DEFAULT_BODY_TEMPLATE_HTML).unwrap())
+        );
+
+        let settings = settings_with(
+            r#""body_template": "default", "body_template_html": "<p>{{message}}</p>""#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.body_template_html,
+            Some(Template::parse("<p>{{message}}</p>").unwrap())
+        );
+
+        let settings = settings_with(r#""body_template": "{{message}}""#).unwrap();
+        assert_eq!(settings.body_template_html, None);
+    }
+
+    #[test]
+    fn test_settings_new_template_fields() {
+        let settings = settings_with(
+            r#""template_fields": "{\"name\": {\"required\": true}, \"company\": {\"variable\": \"company_name\"}}", "template_unknown_fields": "reject""#,
+        )
+        .unwrap();
+        let fields = settings.template_fields.unwrap();
+        assert!(fields["name"].required);
+        assert_eq!(fields["company"].variable.as_deref(), Some("company_name"));
+        assert_eq!(settings.template_unknown_fields, UnknownFields::Reject);
+
+        let settings = settings_with(r#""template_fields": {"name": {}}"#).unwrap();
+        assert_eq!(
+            settings.template_fields.unwrap()["name"],
+            Default::default()
+        );
+        assert_eq!(settings.template_unknown_fields, UnknownFields::Drop);
+
+        assert_eq!(
+            settings_with(r#""template_fields": {"name": {"requird": true}}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'template_fields' setting: unknown field `requird`, expected one of `variable`, `required`, `default`"
+        );
+        assert_eq!(
+            settings_with(r#""template_fields": {"a": {"variable": "b"}, "b": {}}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'template_fields' setting: variable 'b' is used by several fields"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_schema() {
+        let settings =
+            settings_with(r#""schema": "{\"type\": \"object\", \"required\": [\"name\"]}""#)
+                .unwrap();
+        assert_eq!(
+            serde_json::to_value(settings.schema).unwrap(),
+            serde_json::json!({"type": "object", "required": ["name"]})
+        );
+
+        assert_eq!(
+            settings_with(r#""schema": {"type": "strin"}"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid 'schema' setting: unknown type 'strin' at ''"
+        );
+        assert_eq!(
+            settings_with(r#""schema": "{""#).unwrap_err().to_string(),
+            "Invalid 'schema' setting, expected a JSON Schema"
+        );
+    }
+
+    #[test]
+    fn test_settings_new_invalid_address() {
+        assert_eq!(
+            settings_with(r#""cc": "a@example.com, b@""#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid email address 'b@' in 'cc' setting: missing domain after '@'"
+        );
+
+        let result = Settings::from_json(
+            r#"{"api_key": "test_value", "email_from": "a@example.com, b@example.com"}"#,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Expected a single address in 'email_from' setting"
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use serde_json::Value;
+
+use crate::error::{ApiError, ErrorCode};
+use crate::html;
+
+/// A settings value with `{{field}}` placeholders, filled in from the request body.
+/// Nested fields are separated with dots, such as `{{data.company}}`.
+///
+/// `{{#each field}}...{{/each}}` loops over an array or object, where `{{this}}` is the current value
+/// and `{{@key}}` (or `{{@index}}`) its key. `{{#each this}}` at the top level loops over all submitted fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(String),
+    Each(String, Vec<Part>),
+}
+
+/// The value being looped over, and its key.
+struct Scope<'a> {
+    value: &'a Value,
+    key: Option<String>,
+}
+
+impl Template {
+    /// Parses a template, so syntax errors are reported with the settings.
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut rest = source;
+        let parts = parse_parts(&mut rest, None)?;
+        Ok(Self {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    /// Fills in the placeholders. Missing fields are errors, so a half-rendered value is never sent.
+    pub fn render(&self, body_json: &Value) -> anyhow::Result<String> {
+        self.render_with(body_json, &|value| value.to_string())
+    }
+
+    /// Same as [`Template::render`], with HTML-escaped values.
+    pub fn render_html(&self, body_json: &Value) -> anyhow::Result<String> {
+        self.render_with(body_json, &html::escape)
+    }
+
+    fn render_with(
+        &self,
+        body_json: &Value,
+        escape: &dyn Fn(&str) -> String,
+    ) -> anyhow::Result<String> {
+        let mut rendered = String::with_capacity(self.source.len());
+        let root = Scope {
+            value: body_json,
+            key: None,
+        };
+        render_parts(&self.parts, body_json, &root, escape, &mut rendered)?;
+        Ok(rendered)
+    }
+}
+
+/// Parses until the end of the template, or the `{{/each}}` closing the current loop.
+fn parse_parts(rest: &mut &str, each: Option<&str>) -> anyhow::Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow::anyhow!("unterminated '{{{{' placeholder"))?;
+        let tag = rest[start + 2..start + end].trim();
+        *rest = &rest[start + end + 2..];
+
+        if tag == "/each" {
+            return match each {
+                Some(_) => Ok(parts),
+                None => Err(anyhow::anyhow!("unexpected '{{{{/each}}}}'")),
+            };
+        }
+        match tag.strip_prefix("#each ") {
+            Some(field) => {
+                let field = parse_field(field.trim())?;
+                let body = parse_parts(rest, Some(&field))?;
+                parts.push(Part::Each(field, body));
+            }
+            None => parts.push(Part::Field(parse_field(tag)?)),
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+        *rest = "";
+    }
+
+    match each {
+        Some(field) => Err(anyhow::anyhow!(
+            "missing '{{{{/each}}}}' for '{{{{#each {field}}}}}'"
+        )),
+        None => Ok(parts),
+    }
+}
+
+fn parse_field(field: &str) -> anyhow::Result<String> {
+    let valid = match field {
+        "@key" | "@index" => true,
+        _ => {
+            !field.is_empty()
+                && !field.split('.').any(str::is_empty)
+                && field
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        }
+    };
+    if !valid {
+        return Err(anyhow::anyhow!("invalid placeholder '{{{{{field}}}}}'"));
+    }
+    Ok(field.to_string())
+}
+
+fn render_parts(
+    parts: &[Part],
+    body_json: &Value,
+    scope: &Scope,
+    escape: &dyn Fn(&str) -> String,
+    rendered: &mut String,
+) -> anyhow::Result<()> {
+    for part in parts {
+        match part {
+            Part::Text(text) => rendered.push_str(text),
+            Part::Field(field) => {
+                let value = match field.as_str() {
+                    "@key" | "@index" => scope.key.clone().unwrap_or_default(),
+                    _ => format_value(lookup(field, body_json, scope)?),
+                };
+                rendered.push_str(&escape(&value));
+            }
+            Part::Each(field, body) => {
+                let items: Vec<(String, &Value)> = match lookup(field, body_json, scope)? {
+                    Value::Array(values) => values
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| (index.to_string(), value))
+                        .collect(),
+                    Value::Object(values) => values
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value))
+                        .collect(),
+                    _ => {
+                        let message = format!("'{field}' field must be an array or an object");
+                        return Err(ApiError::new(ErrorCode::InvalidField, message)
+                            .with_field(field)
+                            .into());
+                    }
+                };
+                for (key, value) in items {
+                    let scope = Scope {
+                        value,
+                        key: Some(key),
+                    };
+                    render_parts(body, body_json, &scope, escape, rendered)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds a field in the request body, or in the current value for `this`.
+fn lookup<'a>(field: &str, body_json: &'a Value, scope: &Scope<'a>) -> anyhow::Result<&'a Value> {
+    let mut path = field.split('.').peekable();
+    let root = match path.peek() {
+        Some(&"this") => {
+            path.next();
+            scope.value
+        }
+        _ => body_json,
+    };
+    path.try_fold(root, |value, key| value.get(key))
+        .filter(|value| !value.is_null() || field == "this")
+        .ok_or_else(|| {
+            let message = format!("Missing '{field}' field in request body");
+            ApiError::new(ErrorCode::MissingField, message)
+                .with_field(field)
+                .into()
+        })
+}
+
+/// Displays any JSON value: arrays as comma-separated lists, objects as `key: value` pairs.
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::Bool(value) => value.to_string(),
+        Value::Array(values) => values
+            .iter()
+            .map(format_value)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Value::Object(values) => values
+            .iter()
+            .map(|(key, value)| format!("{key}: {}", format_value(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+impl serde::Serialize for Template {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let body = json!({
+            "name": "John",
+            "age": 42,
+            "data": {"company": "ACME"},
+        });
+        let render = |source: &str| Template::parse(source).unwrap().render(&body).unwrap();
+        assert_eq!(render("{{name}} via Website"), "John via Website");
+        assert_eq!(
+            render("{{ name }} ({{age}}) from {{data.company}}"),
+            "John (42) from ACME"
+        );
+        assert_eq!(render("No placeholder"), "No placeholder");
+        assert_eq!(render("{{name}}{{name}}"), "JohnJohn");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn test_render_missing_field() {
+        let template = Template::parse("{{name}} about {{data.topic}}").unwrap();
+        assert_eq!(
+            template
+                .render(&json!({"data": {}}))
+                .unwrap_err()
+                .to_string(),
+            "Missing 'name' field in request body"
+        );
+        assert_eq!(
+            template
+                .render(&json!({"name": "John", "data": {"topic": null}}))
+                .unwrap_err()
+                .to_string(),
+            "Missing 'data.topic' field in request body"
+        );
+        assert_eq!(
+            Template::parse("{{#each items}}{{this}}{{/each}}")
+                .unwrap()
+                .render(&json!({"items": "x"}))
+                .unwrap_err()
+                .to_string(),
+            "'items' field must be an array or an object"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            Template::parse("Hello {{name").unwrap_err().to_string(),
+            "unterminated '{{' placeholder"
+        );
+        assert_eq!(
+            Template::parse("Hello {{}}").unwrap_err().to_string(),
+            "invalid placeholder '{{}}'"
+        );
+        assert_eq!(
+            Template::parse("Hello {{first name}}")
+                .unwrap_err()
+                .to_string(),
+            "invalid placeholder '{{first name}}'"
+        );
+        assert!(Template::parse("{{data..name}}").is_err());
+        assert_eq!(
+            Template::parse("{{#each items}}{{this}}")
+                .unwrap_err()
+                .to_string(),
+            "missing '{{/each}}' for '{{#each items}}'"
+        );
+        assert_eq!(
+            Template::parse("{{this}}{{/each}}")
+                .unwrap_err()
+                .to_string(),
+            "unexpected '{{/each}}'"
+        );
+    }
+
+    #[test]
+    fn test_render_each() {
+        let body = json!({
+            "name": "John",
+            "tags": ["a", "b"],
+            "data": {"company": "ACME", "size": 12},
+        });
+        let render = |source: &str| Template::parse(source).unwrap().render(&body).unwrap();
+        assert_eq!(
+            render("{{#each tags}}{{@index}}={{this}};{{/each}}"),
+            "0=a;1=b;"
+        );
+        assert_eq!(
+            render("{{#each data}}{{@key}}: {{this}} ({{name}})\n{{/each}}"),
+            "company: ACME (John)\nsize: 12 (John)\n"
+        );
+        assert_eq!(
+            render("{{#each this}}{{@key}}={{this}}|{{/each}}"),
+            "name=John|tags=a, b|data=company: ACME, size: 12|"
+        );
+        assert_eq!(
+            render("{{#each data}}{{#each tags}}{{this}}{{/each}}{{/each}}"),
+            "abab"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let body = json!({"name": "<b>John</b> & co", "tags": ["<i>"]});
+        let template =
+            Template::parse("<p>{{name}}</p>{{#each tags}}<li>{{this}}</li>{{/each}}").unwrap();
+        assert_eq!(
+            template.render_html(&body).unwrap(),
+            "<p>&lt;b&gt;John&lt;/b&gt; &amp; co</p><li>&lt;i&gt;</li>"
+        );
+        assert_eq!(
+            template.render(&body).unwrap(),
+            "<p><b>John</b> & co</p><li><i></li>"
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let template = Template::parse("{{name}} via Website").unwrap();
+        assert_eq!(
+            serde_json::to_value(&template).unwrap(),
+            json!("{{name}} via Website")
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// How a field of the request's `data` object is passed to the dynamic template.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    /// template variable name, defaults to the field name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variable: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub required: bool,
+    /// value used when the field is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownFields {
+    /// fields that are not listed are silently removed
+    #[default]
+    Drop,
+    /// fields that are not listed make the request fail
+    Reject,
+}
+
+/// The accepted fields, by request field name.
+pub type TemplateFields = BTreeMap<String, FieldSpec>;
+
+/// Checks the field definitions, so mistakes are reported with the settings.
+pub fn validate(fields: &TemplateFields) -> anyhow::Result<()> {
+    let mut variables = HashSet::new();
+    for (name, spec) in fields {
+        if spec.required && spec.default.is_some() {
+            return Err(anyhow::anyhow!(
+                "field '{name}' can't be both required and have a default value"
+            ));
+        }
+        let variable = spec.variable.as_deref().unwrap_or(name);
+        if variable.trim().is_empty() {
+            return Err(anyhow::anyhow!("field '{name}' has an empty variable name"));
+        }
+        if !variables.insert(variable) {
+            return Err(anyhow::anyhow!(
+                "variable '{variable}' is used by several fields"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the template variables from the request's `data` object:
+/// only listed fields are kept, renamed, with defaults for missing ones.
+pub fn apply(
+    data: Option<&Value>,
+    fields: &TemplateFields,
+    unknown_fields: &UnknownFields,
+) -> anyhow::Result<Value> {
+    let empty = Map::new();
+    let data = match data {
+        Some(Value::Object(data)) => data,
+        Some(Value::Null) | None => &empty,
+        Some(_) => {
+            let message = "'data' field must be an object";
+            return Err(ApiError::new(ErrorCode::InvalidField, message)
+                .with_field("data")
+                .into());
+        }
+    };
+
+    if *unknown_fields == UnknownFields::Reject {
+        if let Some(name) = data.keys().find(|name| !fields.contains_key(*name)) {
+            let message = format!("Unknown 'data.{name}' field in request body");
+            return Err(ApiError::new(ErrorCode::InvalidField, message)
+                .with_field(&format!("data.{name}"))
+                .into());
+        }
+    }
+
+    let mut variables = Map::new();
+    for (name, spec) in fields {
+        let value = match data.get(name).filter(|value| !value.is_null()) {
+            Some(value) => value.clone(),
+            None if spec.required => {
+                let message = format!("Missing required 'data.{name}' field in request body");
+                return Err(ApiError::new(ErrorCode::MissingField, message)
+                    .with_field(&format!("data.{name}"))
+                    .into());
+            }
+            None => match &spec.default {
+                Some(default) => default.clone(),
+                None => continue,
+            },
+        };
+        let variable = spec.variable.clone().unwrap_or_else(|| name.to_string());
+        variables.insert(variable, value);
+    }
+    Ok(Value::Object(variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields() -> TemplateFields {
+        serde_json::from_value(json!({
+            "name": {"required": true},
+            "company": {"variable": "company_name", "default": "n/a"},
+            "topic": {},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let data = json!({"name": "John", "company": "ACME", "topic": "pricing", "is_admin": true});
+        assert_eq!(
+            apply(Some(&data), &fields(), &UnknownFields::Drop).unwrap(),
+            json!({"name": "John", "company_name": "ACME", "topic": "pricing"})
+        );
+
+        let data = json!({"name": "John", "company": null});
+        assert_eq!(
+            apply(Some(&data), &fields(), &UnknownFields::Drop).unwrap(),
+            json!({"name": "John", "company_name": "n/a"})
+        );
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let data = json!({"name": "John", "is_admin": true});
+        assert_eq!(
+            apply(Some(&data), &fields(), &UnknownFields::Reject)
+                .unwrap_err()
+                .to_string(),
+            "Unknown 'data.is_admin' field in request body"
+        );
+
+        assert_eq!(
+            apply(None, &fields(), &UnknownFields::Drop)
+                .unwrap_err()
+                .to_string(),
+            "Missing required 'data.name' field in request body"
+        );
+
+        assert_eq!(
+            apply(Some(&json!("John")), &fields(), &UnknownFields::Drop)
+                .unwrap_err()
+                .to_string(),
+            "'data' field must be an object"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&fields()).is_ok());
+
+        let fields: TemplateFields = serde_json::from_value(json!({
+            "name": {"required": true, "default": "John"},
+        }))
+        .unwrap();
+        assert_eq!(
+            validate(&fields).unwrap_err().to_string(),
+            "field 'name' can't be both required and have a default value"
+        );
+
+        let fields: TemplateFields = serde_json::from_value(json!({
+            "company": {"variable": "name"},
+            "name": {},
+        }))
+        .unwrap();
+        assert_eq!(
+            validate(&fields).unwrap_err().to_string(),
+            "variable 'name' is used by several fields"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::world::bindings::wasi::clocks::monotonic_clock;
+
+/// Outgoing calls to email services, waiting between retries, and the clock bounding them.
+/// Implemented with WASI in the component, and faked in tests.
+pub trait Transport {
+    fn post(&self, request: &HttpRequest) -> anyhow::Result<HttpResponse>;
+
+    /// Blocks until the duration has elapsed.
+    fn sleep(&self, duration: Duration);
+
+    /// Monotonic time, only meaningful relative to other calls.
+    fn now(&self) -> Duration;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub timeouts: Timeouts,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// to establish the connection
+    pub connect: Duration,
+    /// for the whole exchange, until the response body has been read, also used to
+    /// budget retries and failover when the transport can't interrupt a call
+    pub total: Duration,
+}
+
+/// The request didn't complete within its timeouts.
+#[derive(Debug)]
+pub struct Timeout;
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request timed out")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+impl HttpRequest {
+    /// A JSON POST request.
+    pub fn json(url: &str, body: Vec<u8>, timeouts: Timeouts) -> Self {
+        Self {
+            url: url.to_string(),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body,
+            timeouts,
+        }
+    }
+
+    pub fn bearer(mut self, token: &str) -> Self {
+        self.headers
+            .push(("Authorization".to_string(), format!("Bearer {token}")));
+        self
+    }
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.get(&name.to_ascii_lowercase()).cloned()
+    }
+}
+
+/// The component's transport: waki for HTTP, and the WASI monotonic clock.
+/// waki applies the connect timeout, but can't interrupt a call once connected: the overall
+/// deadline is enforced around calls, as no attempt is started once it has passed.
+pub struct WasiTransport;
+
+impl Transport for WasiTransport {
+    fn post(&self, request: &HttpRequest) -> anyhow::Result<HttpResponse> {
+        let mut builder = waki::Client::new()
+            .post(&request.url)
+            .connect_timeout(request.timeouts.connect);
+        for (name, value) in &request.headers {
+            let name = waki::header::HeaderName::from_bytes(name.as_bytes())?;
+            builder = builder.header(name, value.as_str());
+        }
+        let started = self.now();
+        let response = builder.body(request.body.clone()).send().map_err(error)?;
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let status = response.status_code();
+        let body = response.body().unwrap_or_default();
+        let elapsed = self.now().saturating_sub(started);
+        if elapsed > request.timeouts.total {
+            eprintln!(
+                "Request to {} took {elapsed:?}, more than the {:?} allowed",
+                request.url, request.timeouts.total
+            );
+        }
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn sleep(&self, duration: Duration) {
+        monotonic_clock::subscribe_duration(nanos(duration)).block();
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_nanos(monotonic_clock::now())
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Timeouts reported by the runtime are [`Timeout`] errors too.
+fn error(error: anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<waki::ErrorCode>() {
+        Some(
+            waki::ErrorCode::DnsTimeout
+            | waki::ErrorCode::ConnectionTimeout
+            | waki::ErrorCode::ConnectionReadTimeout
+            | waki::ErrorCode::ConnectionWriteTimeout
+            | waki::ErrorCode::HttpResponseTimeout,
+        ) => Timeout.into(),
+        _ => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_timeouts() {
+        assert!(error(waki::ErrorCode::ConnectionTimeout.into()).is::<Timeout>());
+        assert!(error(waki::ErrorCode::HttpResponseTimeout.into()).is::<Timeout>());
+        assert!(!error(waki::ErrorCode::ConnectionRefused.into()).is::<Timeout>());
+        assert!(!error(anyhow::anyhow!("Invalid header")).is::<Timeout>());
+    }
+
+    #[test]
+    fn test_request() {
+        let timeouts = Timeouts {
+            connect: Duration::from_secs(1),
+            total: Duration::from_secs(2),
+        };
+        let request =
+            HttpRequest::json("https://example.com", b"{}".to_vec(), timeouts).bearer("key");
+        assert_eq!(
+            request.headers,
+            vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("Authorization".to_string(), "Bearer key".to_string()),
+            ]
+        );
+        assert_eq!(request.timeouts, timeouts);
+    }
+}